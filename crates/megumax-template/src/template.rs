@@ -29,6 +29,14 @@ impl Template {
 	pub fn set(&mut self, key: String, value: String) {
 		self.keys.insert(key, value);
 	}
+
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.keys.get(key).map(String::as_str)
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+		self.keys.iter()
+	}
 }
 
 impl FromIterator<(String, String)> for Template {
@@ -68,6 +76,10 @@ impl Pool {
 		list.push(value.into());
 	}
 
+	pub fn iter(&self) -> impl Iterator<Item = (&String, &[String])> {
+		self.pool.iter().map(|(k, v)| (k, v.as_slice()))
+	}
+
 	pub fn intersect(&self, keys: &[String]) -> Result<Vec<&[String]>, KeyLookUpError> {
 		let mut result = Vec::new();
 
@@ -93,13 +105,14 @@ impl Pool {
 	pub fn template_resources<P: Into<String>>(
 		&self,
 		path: P,
-	) -> Result<Resources, KeyLookUpError> {
+	) -> Result<Resources<'_>, KeyLookUpError> {
 		let path = path.into();
+		let mut seen = HashSet::new();
+		// De-duplicate keys while keeping their order in the path, so variants come out in a stable order.
 		let keys = self
 			.capture(&path)
 			.into_iter()
-			.collect::<HashSet<_>>() // De-duplicate keys
-			.into_iter()
+			.filter(|key| seen.insert(key.clone()))
 			.collect::<Vec<_>>();
 		let list = self.intersect(&keys)?;
 		let inner = Solver::new(list, keys);
@@ -156,6 +169,32 @@ mod tests {
 		assert_eq!(result, expect);
 	}
 
+	#[test]
+	fn stable_variant_order() {
+		let mut pool = Pool::default_rule();
+		pool.append("[color]", "red");
+		pool.append("[color]", "blue");
+		pool.append("[shape]", "circle");
+		pool.append("[shape]", "square");
+
+		let path = "[shape]/[color]_[shape]";
+
+		let resources = pool.template_resources(path).unwrap();
+
+		let result: Vec<_> = resources.map(|r| r.path).collect();
+		let expect: Vec<_> = vec![
+			"circle/red_circle",
+			"circle/blue_circle",
+			"square/red_square",
+			"square/blue_square",
+		]
+		.into_iter()
+		.map(PathBuf::from)
+		.collect();
+
+		assert_eq!(result, expect);
+	}
+
 	#[test]
 	fn empty_template() {
 		let pool = Pool::default_rule();
@@ -1,6 +1,6 @@
 use std::iter::FusedIterator;
 
-pub fn variant<S, T>(list: &[S]) -> Variant<T>
+pub fn variant<S, T>(list: &[S]) -> Variant<'_, T>
 where
 	S: AsRef<[T]>,
 {
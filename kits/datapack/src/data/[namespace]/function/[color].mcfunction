@@ -0,0 +1,2 @@
+# Generated once for every value of the `color` pool in megu.toml.
+say Function number [nth] says [color]
@@ -0,0 +1 @@
+tellraw @a "[description] loaded"
@@ -1,6 +1,27 @@
+use crate::config::Config;
+use crate::core::Link;
+use crate::error::{SourceError, SourceErrors};
 use crate::utils::check_expression_block;
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+pub mod clean;
+pub mod diff;
+mod dry_run;
+pub mod event;
+pub mod explain;
+pub mod init;
+pub mod interrupt;
+pub mod lint;
 mod message;
+pub mod migrate;
+pub mod render;
+pub mod report;
+pub mod verify;
+pub mod watch;
+pub mod workspace;
 
 #[cfg(not(feature = "async"))]
 #[path = "app/sync.rs"]
@@ -11,3 +32,107 @@ mod imports;
 mod imports;
 
 pub use imports::*;
+
+/// Options that change how a project is built, independent of its config.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+	/// Clear the output directory even if it wasn't created by megumax.
+	pub force: bool,
+	/// Print what would be generated instead of building.
+	pub dry_run: bool,
+	pub message_format: MessageFormat,
+	/// Sources known to have changed since the previous build, relative to the source directory. Every other source
+	/// keeps the outputs recorded in the manifest without being read. Every source is checked when `None`.
+	pub changed: Option<BTreeSet<PathBuf>>,
+	/// Carry on past failing sources and report every error at the end, the output directory is still left untouched.
+	pub keep_going: bool,
+}
+
+impl BuildOptions {
+	/// Whether the source of `link` has to be checked for changes, a changed directory covers every file in it.
+	fn is_changed(&self, config: &Config, link: &Link) -> bool {
+		let changed = match &self.changed {
+			Some(changed) => changed,
+			None => return true,
+		};
+
+		match link.from.strip_prefix(&config.source) {
+			Ok(relative) => changed.iter().any(|path| relative.starts_with(path)),
+			Err(_) => true,
+		}
+	}
+}
+
+/// Errors of failing sources, collected instead of stopping the build when keeping going.
+struct Failures {
+	keep_going: bool,
+	errors: Vec<SourceError>,
+}
+
+impl Failures {
+	fn new(keep_going: bool) -> Self {
+		Self {
+			keep_going,
+			errors: Vec::new(),
+		}
+	}
+
+	/// The value of `result`, or `None` once its error is recorded against the source at `path`.
+	///
+	/// The error is returned right away unless keeping going.
+	fn record<T>(&mut self, path: &Path, result: Result<T>) -> Result<Option<T>> {
+		match result {
+			Ok(value) => Ok(Some(value)),
+			Err(error) if self.keep_going => {
+				self.errors.push(SourceError {
+					path: path.to_path_buf(),
+					error,
+				});
+				Ok(None)
+			}
+			Err(error) => Err(error),
+		}
+	}
+
+	/// Fail with every recorded error.
+	fn finish(self) -> Result<()> {
+		if self.errors.is_empty() {
+			Ok(())
+		} else {
+			Err(SourceErrors(self.errors).into())
+		}
+	}
+}
+
+/// Output of a single resource.
+struct Rendered {
+	content: Vec<u8>,
+	/// The source isn't text, so it was copied as is.
+	binary: bool,
+}
+
+/// How progress and results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+	/// Colored text meant to be read by people.
+	#[default]
+	Human,
+	/// JSON printed to stdout, meant to be read by other programs.
+	Json,
+}
+
+impl MessageFormat {
+	pub const VARIANTS: &'static [&'static str] = &["human", "json"];
+}
+
+impl FromStr for MessageFormat {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"human" => Ok(Self::Human),
+			"json" => Ok(Self::Json),
+			_ => bail!("Unknown message format `{}`, expected `human` or `json`", s),
+		}
+	}
+}
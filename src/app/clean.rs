@@ -0,0 +1,131 @@
+use super::{message, BuildOptions};
+use crate::config::{CleanMode, Config, MARKER_FILE};
+use crate::core::manifest::{remove_empty_parents, Manifest, MANIFEST_FILE};
+use crate::core::staging::{previous_dir, staging_dir};
+use crate::share::list_files;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+/// Files of the output directory that `megu clean` removes, relative to the output directory.
+///
+/// With `clean = "all"` megumax owns the whole directory, otherwise only what the previous build recorded.
+pub fn managed_files(config: &Config, force: bool) -> Result<Vec<PathBuf>> {
+	if !config.dest.is_dir() {
+		return Ok(Vec::new());
+	}
+
+	let files = match (config.clean, Manifest::load(config)) {
+		(CleanMode::All, _) => {
+			config.check_build_dir(force)?;
+			list_files(&config.dest)?
+		}
+		(CleanMode::Prune, Some(manifest)) => {
+			let reserved = [PathBuf::from(MARKER_FILE), PathBuf::from(MANIFEST_FILE)];
+			let outputs = manifest.outputs().into_iter().map(PathBuf::from);
+			outputs.chain(reserved.iter().cloned()).collect()
+		}
+		(CleanMode::Prune, None) => {
+			log::warn!(
+				"No record of a previous build in `{}`, nothing to clean",
+				config.dest.display()
+			);
+			Vec::new()
+		}
+	};
+
+	let files = files
+		.into_iter()
+		.filter(|path| !config.is_preserved(path))
+		.filter(|path| config.dest.join(path).is_file())
+		.collect();
+
+	Ok(files)
+}
+
+/// Remove the generated files of the project, and the output directory itself once it's empty.
+pub fn clean_project(config: &Config, options: &BuildOptions) -> Result<()> {
+	if !options.dry_run {
+		config.check_dest()?;
+	}
+
+	let files = managed_files(config, options.force)?;
+
+	for relative in &files {
+		message::removed(relative, options.dry_run);
+
+		if options.dry_run {
+			continue;
+		}
+
+		let path = config.dest.join(relative);
+		fs::remove_file(&path).with_context(|| format!("Removing `{}`", path.display()))?;
+		remove_empty_parents(&path, &config.dest);
+	}
+
+	if !options.dry_run {
+		// Only succeeds when nothing else is left.
+		let _ = fs::remove_dir(&config.dest);
+		let _ = fs::remove_dir_all(staging_dir(&config.dest));
+		let _ = fs::remove_dir_all(previous_dir(&config.dest));
+	}
+
+	message::clean_summary(files.len(), options.dry_run);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+
+	#[test]
+	fn only_remove_managed_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.with_clean(CleanMode::Prune)
+				.build();
+
+		let build = &config.dest;
+		fs::create_dir_all(build.join("data")).unwrap();
+		fs::write(build.join("data").join("a.txt"), "a").unwrap();
+		fs::write(build.join("pack.mcmeta"), "{}").unwrap();
+		config.mark_build_dir().unwrap();
+
+		let mut manifest = Manifest::new(&config);
+		let mut entry = crate::core::manifest::SourceEntry::new(String::new());
+		entry.outputs.insert("data/a.txt".into(), String::new());
+		manifest.sources.insert("a.txt".into(), entry);
+		manifest.save(&config).unwrap();
+
+		let dry_run = BuildOptions {
+			dry_run: true,
+			..BuildOptions::default()
+		};
+		clean_project(&config, &dry_run).unwrap();
+		assert!(build.join("data").join("a.txt").is_file());
+
+		clean_project(&config, &BuildOptions::default()).unwrap();
+		assert!(!build.join("data").exists());
+		assert!(!build.join(MARKER_FILE).exists());
+		assert!(!build.join(MANIFEST_FILE).exists());
+		assert!(build.join("pack.mcmeta").is_file());
+	}
+
+	#[test]
+	fn refuse_unmarked_directory() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.build();
+
+		fs::create_dir_all(&config.dest).unwrap();
+		fs::write(config.dest.join("important.txt"), "").unwrap();
+
+		assert!(clean_project(&config, &BuildOptions::default()).is_err());
+		assert!(config.dest.join("important.txt").is_file());
+	}
+}
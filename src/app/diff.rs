@@ -0,0 +1,150 @@
+use super::{message, plan_project, render_project};
+use crate::config::Config;
+use crate::core::compare::{compare, Change};
+use crate::core::manifest::hash_bytes;
+use anyhow::{Context, Result};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// Render the project in memory and compare it to the output directory, which is never modified.
+pub fn changes(config: &Config) -> Result<Vec<Change>> {
+	let plan = plan_project(config)?;
+	plan.check_collisions(config)?;
+
+	let outputs = render_project(config, plan)?;
+	compare(config, outputs)
+}
+
+/// Write how a fresh build differs from the output directory to `writer`, as a unified diff of the output directory.
+///
+/// Returns whether there are any differences.
+pub fn diff_project<W: Write>(config: &Config, mut writer: W) -> Result<bool> {
+	message::config_info(config);
+	let changes = changes(config)?;
+
+	let result = changes
+		.iter()
+		.try_for_each(|change| write_change(change, &mut writer))
+		.and_then(|_| writer.flush());
+
+	match result {
+		// The reader went away, like a pager closed before the end, the differences are still there.
+		Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
+		result => result.with_context(|| "Writing diff")?,
+	}
+
+	message::diff_summary(changes.len());
+	Ok(!changes.is_empty())
+}
+
+/// Write a single change the way `git diff` does, added and removed files are compared to `/dev/null`.
+fn write_change<W: Write>(change: &Change, writer: &mut W) -> std::io::Result<()> {
+	let path = change.path();
+
+	let (old_name, new_name, old, new): (_, _, &[u8], &[u8]) = match change {
+		Change::Added { new, .. } => (null(), name("b", path), &[], new),
+		Change::Removed { old, .. } => (name("a", path), null(), old, &[]),
+		Change::Modified { old, new, .. } => (name("a", path), name("b", path), old, new),
+	};
+
+	match (as_text(old), as_text(new)) {
+		(Some(old), Some(new)) => {
+			write!(
+				writer,
+				"{}",
+				message::unified_diff(old, new, &old_name, &new_name)
+			)
+		}
+		_ => writeln!(
+			writer,
+			"Binary files {} and {} differ: {} -> {}",
+			old_name,
+			new_name,
+			describe_bytes(old),
+			describe_bytes(new)
+		),
+	}
+}
+
+fn name(prefix: &str, path: &Path) -> String {
+	format!("{}/{}", prefix, path.to_string_lossy().replace('\\', "/"))
+}
+
+fn null() -> String {
+	"/dev/null".to_owned()
+}
+
+fn as_text(content: &[u8]) -> Option<&str> {
+	if content.contains(&0) {
+		return None;
+	}
+
+	std::str::from_utf8(content).ok()
+}
+
+fn describe_bytes(content: &[u8]) -> String {
+	if content.is_empty() {
+		return "nothing".to_owned();
+	}
+
+	let hash = hash_bytes(content);
+	format!("{} bytes ({})", content.len(), &hash[..8])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+	use std::fs;
+
+	#[test]
+	fn unified_diff_of_output_directory() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.build();
+
+		let (src, build) = (&config.source, &config.dest);
+		fs::create_dir_all(src).unwrap();
+		fs::create_dir_all(build).unwrap();
+		fs::write(src.join("changed.txt"), "one\ntwo\nthree\n").unwrap();
+		fs::write(build.join("changed.txt"), "one\n2\nthree\n").unwrap();
+		fs::write(src.join("added.txt"), "new\n").unwrap();
+		fs::write(build.join("removed.txt"), "old\n").unwrap();
+		fs::write(src.join("image.bin"), [0, 1, 2]).unwrap();
+
+		let mut output = Vec::new();
+		assert!(diff_project(&config, &mut output).unwrap());
+
+		let expect = "\
+--- /dev/null
++++ b/added.txt
+@@ -0,0 +1 @@
++new
+--- a/changed.txt
++++ b/changed.txt
+@@ -1,3 +1,3 @@
+ one
+-2
++two
+ three
+Binary files /dev/null and b/image.bin differ: nothing -> 3 bytes (";
+		let output = String::from_utf8(output).unwrap();
+		assert!(output.starts_with(expect), "{}", output);
+		assert!(
+			output.ends_with("--- a/removed.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-old\n"),
+			"{}",
+			output
+		);
+
+		fs::remove_file(build.join("removed.txt")).unwrap();
+		fs::write(build.join("changed.txt"), "one\ntwo\nthree\n").unwrap();
+		fs::write(build.join("added.txt"), "new\n").unwrap();
+		fs::write(build.join("image.bin"), [0, 1, 2]).unwrap();
+
+		let mut output = Vec::new();
+		assert!(!diff_project(&config, &mut output).unwrap());
+		assert!(output.is_empty());
+	}
+}
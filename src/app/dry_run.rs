@@ -0,0 +1,102 @@
+use super::{message, MessageFormat};
+use crate::config::{Config, MergeStrategy};
+use crate::core::manifest::output_key;
+use crate::core::plan::Plan;
+use crate::core::special::variant;
+use crate::share::print_line;
+use anyhow::Result;
+use megumax_template::Resource;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+struct PlanReport<'a> {
+	source: &'a Path,
+	dest: &'a Path,
+	sources: Vec<SourceReport<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct SourceReport<'a> {
+	pub path: &'a Path,
+	pub outputs: Vec<OutputReport<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct OutputReport<'a> {
+	pub path: &'a Path,
+	pub nth: usize,
+	/// Template values of the variant, keyed by the pool name without brackets.
+	pub variant: BTreeMap<&'a str, &'a str>,
+	/// Set when several outputs are combined into this one.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub merge: Option<MergeStrategy>,
+}
+
+impl<'a> OutputReport<'a> {
+	pub fn new(resource: &'a Resource, nth: usize, merge: Option<MergeStrategy>) -> Self {
+		Self {
+			path: &resource.path,
+			nth,
+			variant: variant(resource),
+			merge,
+		}
+	}
+}
+
+/// Print every source along with the outputs it would produce, without touching the output directory.
+pub fn print_plan(config: &Config, plan: &Plan, format: MessageFormat) -> Result<()> {
+	let merges = plan.merge_targets(config);
+
+	let sources = plan.sources.iter().map(|planned| {
+		let outputs = planned
+			.resources
+			.iter()
+			.enumerate()
+			.map(|(nth, resource)| {
+				OutputReport::new(resource, nth, merges.get(&resource.path).copied())
+			})
+			.collect();
+
+		SourceReport {
+			path: &planned.link.from,
+			outputs,
+		}
+	});
+
+	match format {
+		MessageFormat::Human => {
+			print_sources(config, sources);
+			message::plan_summary(plan.sources.len(), plan.resource_count());
+		}
+		MessageFormat::Json => {
+			let report = PlanReport {
+				source: &config.source,
+				dest: &config.dest,
+				sources: sources.collect(),
+			};
+
+			print_line(&serde_json::to_string_pretty(&report)?)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Print each source followed by its outputs and their variant values.
+pub(super) fn print_sources<'a, I>(config: &Config, sources: I)
+where
+	I: IntoIterator<Item = SourceReport<'a>>,
+{
+	for source in sources {
+		message::planned_source(source.path);
+
+		for output in source.outputs {
+			let relative = output_key(config, output.path);
+			message::planned_output(&relative, &output.variant, output.nth, output.merge);
+		}
+
+		message::newline();
+	}
+}
@@ -0,0 +1,149 @@
+//! Events of a build printed with `--message-format json`, one JSON object per line on stdout.
+//!
+//! Every event has an `event` field naming it, its other fields depend on the event:
+//!
+//! | `event`              | Fields                                                                                       | Printed                                       |
+//! | -------------------- | -------------------------------------------------------------------------------------------- | --------------------------------------------- |
+//! | `build-started`      | `config`, `source`, `dest`                                                                   | Once the config is loaded.                    |
+//! | `source`             | `path`, `status` (`rendered` or `unchanged`)                                                 | For every source file.                        |
+//! | `resource`           | `source`, `path`, `nth`, `variant`                                                           | For every output rendered from a source.      |
+//! | `binary`             | `source`, `path`, `nth`, `variant`                                                           | For every output copied from a binary source. |
+//! | `merged`             | `path`, `strategy`                                                                           | For every output combined from several ones.  |
+//! | `warning`            | `message`                                                                                    | Whenever something looks wrong.               |
+//! | `error`              | `message`                                                                                    | When the command fails.                       |
+//! | `build-finished`     | `sources`, `rendered`, `unchanged`, `resources`, `binaries`, `merged`, `bytes`, `elapsed_ms` | Once the build succeeded.                     |
+//! | `member`             | `name`                                                                                       | Before building each workspace member.        |
+//! | `workspace-finished` | `total`, `failed`                                                                            | Once every workspace member was built.        |
+//! | `watching`           | `config`, `source`                                                                           | When `megu watch` waits for changes.          |
+//! | `changed`            | `path`                                                                                       | For every change `megu watch` rebuilds.       |
+//! | `paused`             | (none)                                                                                       | When `megu watch` can't load the config.      |
+//!
+//! Paths are written the way the config resolves them, like `build/red.txt`. `variant` maps the name of each pool
+//! used in the source path, without brackets, to its value. `strategy` is one of the `[[build.merge]]` strategies.
+//! `bytes` only counts the outputs that were written, the ones that already had the same content are left alone.
+//! `failed` lists the names of the workspace members that failed to build.
+//!
+//! Fields are only ever added, so consumers should ignore the ones they don't know. Warnings are also printed as
+//! text on stderr. `megu plan`, `explain`, `why` and `lint` print a single JSON document instead, and the other
+//! commands keep printing text.
+
+use crate::config::MergeStrategy;
+use crate::share::print_line;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+	BuildStarted {
+		config: &'a Path,
+		source: &'a Path,
+		dest: &'a Path,
+	},
+	Source {
+		path: &'a Path,
+		status: SourceStatus,
+	},
+	Resource(Output<'a>),
+	Binary(Output<'a>),
+	Merged {
+		path: &'a Path,
+		strategy: MergeStrategy,
+	},
+	Warning {
+		message: String,
+	},
+	Error {
+		message: String,
+	},
+	BuildFinished(Summary),
+	Member {
+		name: &'a str,
+	},
+	WorkspaceFinished {
+		total: usize,
+		failed: &'a [&'a str],
+	},
+	Watching {
+		config: &'a Path,
+		source: &'a Path,
+	},
+	Changed {
+		path: &'a Path,
+	},
+	Paused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceStatus {
+	Rendered,
+	/// Neither the source nor the config changed since the previous build.
+	Unchanged,
+}
+
+/// Output of a source, with the variant that produced it.
+#[derive(Debug, Serialize)]
+pub struct Output<'a> {
+	pub source: &'a Path,
+	pub path: &'a Path,
+	pub nth: usize,
+	pub variant: BTreeMap<&'a str, &'a str>,
+}
+
+/// Counts of a successful build.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+	pub sources: usize,
+	pub rendered: usize,
+	pub unchanged: usize,
+	/// Outputs of the rendered sources, including binary copies.
+	pub resources: usize,
+	pub binaries: usize,
+	pub merged: usize,
+	/// Size of the outputs written, the ones that already had the same content aren't counted.
+	pub bytes: u64,
+	/// Duration of the whole build.
+	pub elapsed_ms: u64,
+}
+
+/// Print `event` as a single line of JSON.
+pub fn emit(event: &Event) {
+	match serde_json::to_string(event) {
+		Ok(line) => {
+			if let Err(e) = print_line(&line) {
+				log::debug!("Failed to print {:?}: {:#}", event, e);
+			}
+		}
+		Err(e) => log::debug!("Failed to serialize {:?}: {}", event, e),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn event_schema() {
+		let mut variant = BTreeMap::new();
+		variant.insert("color", "red");
+		let event = Event::Resource(Output {
+			source: Path::new("src/[color].txt"),
+			path: Path::new("build/red.txt"),
+			nth: 0,
+			variant,
+		});
+
+		assert_eq!(
+			serde_json::to_string(&event).unwrap(),
+			r#"{"event":"resource","source":"src/[color].txt","path":"build/red.txt","nth":0,"variant":{"color":"red"}}"#
+		);
+
+		let event = Event::BuildFinished(Summary::default());
+		assert_eq!(
+			serde_json::to_string(&event).unwrap(),
+			r#"{"event":"build-finished","sources":0,"rendered":0,"unchanged":0,"resources":0,"binaries":0,"merged":0,"bytes":0,"elapsed_ms":0}"#
+		);
+	}
+}
@@ -0,0 +1,151 @@
+use super::dry_run::{print_sources, OutputReport, SourceReport};
+use super::{plan_project, MessageFormat};
+use crate::config::Config;
+use crate::core::manifest::output_key;
+use crate::core::Link;
+use crate::share::{absolute, print_line};
+use crate::toml::diagnostic::suggest;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// List every output the source at `file` produces, with the variant values and `[nth]` each one gets.
+pub fn explain(config: &Config, file: &Path, format: MessageFormat) -> Result<()> {
+	let source = absolute(&config.source)?;
+	let relative = absolute(file)?
+		.strip_prefix(&source)
+		.map(Path::to_path_buf)
+		.with_context(|| {
+			format!(
+				"`{}` isn't inside the source directory `{}`",
+				file.display(),
+				config.source.display()
+			)
+		})?;
+
+	let from = config.source.join(relative);
+	let link = Link::new(from.clone(), config.replace_prefix(&from)?);
+	let resources: Vec<_> = link.to_resources(&config.template)?.collect();
+
+	let outputs = resources
+		.iter()
+		.enumerate()
+		.map(|(nth, resource)| OutputReport::new(resource, nth, None))
+		.collect();
+
+	let report = SourceReport {
+		path: &link.from,
+		outputs,
+	};
+
+	print(config, vec![report], format)
+}
+
+/// Find the sources and variants producing the output at `output`.
+pub fn why(config: &Config, output: &Path, format: MessageFormat) -> Result<()> {
+	let dest = absolute(&config.dest)?;
+	let target = absolute(output)?;
+
+	let plan = plan_project(config)?;
+	let merges = plan.merge_targets(config);
+	let mut reports = Vec::new();
+
+	for planned in &plan.sources {
+		let outputs: Vec<_> = planned
+			.resources
+			.iter()
+			.enumerate()
+			.filter(|(_, resource)| dest.join(output_key(config, &resource.path)) == target)
+			.map(|(nth, resource)| {
+				OutputReport::new(resource, nth, merges.get(&resource.path).copied())
+			})
+			.collect();
+
+		if !outputs.is_empty() {
+			reports.push(SourceReport {
+				path: &planned.link.from,
+				outputs,
+			});
+		}
+	}
+
+	if reports.is_empty() {
+		let outputs: Vec<_> = plan
+			.sources
+			.iter()
+			.flat_map(|planned| &planned.resources)
+			.map(|resource| {
+				output_key(config, &resource.path)
+					.to_string_lossy()
+					.into_owned()
+			})
+			.collect();
+		let candidates: Vec<_> = outputs.iter().map(String::as_str).collect();
+
+		let wanted = target
+			.strip_prefix(&dest)
+			.unwrap_or(&target)
+			.to_string_lossy();
+		match suggest(&wanted, &candidates) {
+			Some(similar) => bail!(
+				"No source produces `{}`, did you mean `{}`?",
+				output.display(),
+				config.dest.join(similar).display()
+			),
+			None => bail!("No source produces `{}`", output.display()),
+		}
+	}
+
+	print(config, reports, format)
+}
+
+fn print(config: &Config, reports: Vec<SourceReport>, format: MessageFormat) -> Result<()> {
+	match format {
+		MessageFormat::Human => {
+			print_sources(config, reports);
+			Ok(())
+		}
+		MessageFormat::Json => print_line(&serde_json::to_string_pretty(&reports)?),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+	use megumax_template::Pool;
+	use std::fs;
+
+	#[test]
+	fn find_producing_source() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let mut pool = Pool::default_rule();
+		pool.insert("[color]".into(), vec!["red".into(), "blue".into()]);
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.with_template(pool)
+				.build();
+
+		let file = root.join("src").join("[color]").join("[color].md");
+		fs::create_dir_all(file.parent().unwrap()).unwrap();
+		fs::write(&file, "[color]").unwrap();
+
+		let format = MessageFormat::Json;
+		explain(&config, &file, format).unwrap();
+		why(
+			&config,
+			&root.join("build").join("red").join("red.md"),
+			format,
+		)
+		.unwrap();
+
+		let err = why(
+			&config,
+			&root.join("build").join("red").join("rde.md"),
+			format,
+		)
+		.unwrap_err();
+		assert!(err.to_string().contains("did you mean"), "{}", err);
+		assert!(explain(&config, &root.join("other.md"), format).is_err());
+	}
+}
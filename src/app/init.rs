@@ -0,0 +1,323 @@
+use super::message;
+use crate::config::CONFIG_FILE;
+use crate::toml::migrate::CURRENT_VERSION;
+use anyhow::{bail, Context, Result};
+use megumax_template::Template;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const DEFAULT_SRC: &str = "src/";
+const DEFAULT_OUTPUT: &str = "build/";
+
+/// Embedded file of a starter kit, its path is relative to the source directory.
+type KitFile = (&'static str, &'static str);
+
+const DEFAULT_CONFIG: &str = include_str!("../../kits/default/megu.toml");
+
+const DATAPACK_CONFIG: &str = include_str!("../../kits/datapack/megu.toml");
+const DATAPACK_FILES: &[KitFile] = &[
+	(
+		"pack.mcmeta",
+		include_str!("../../kits/datapack/src/pack.mcmeta"),
+	),
+	(
+		"data/minecraft/tags/function/load.json",
+		include_str!("../../kits/datapack/src/data/minecraft/tags/function/load.json"),
+	),
+	(
+		"data/[namespace]/function/load.mcfunction",
+		include_str!("../../kits/datapack/src/data/[namespace]/function/load.mcfunction"),
+	),
+	(
+		"data/[namespace]/function/[color].mcfunction",
+		include_str!("../../kits/datapack/src/data/[namespace]/function/[color].mcfunction"),
+	),
+];
+
+const RESOURCEPACK_CONFIG: &str = include_str!("../../kits/resourcepack/megu.toml");
+const RESOURCEPACK_FILES: &[KitFile] = &[
+	(
+		"pack.mcmeta",
+		include_str!("../../kits/resourcepack/src/pack.mcmeta"),
+	),
+	(
+		"assets/[namespace]/lang/en_us.json",
+		include_str!("../../kits/resourcepack/src/assets/[namespace]/lang/en_us.json"),
+	),
+];
+
+/// Starter layout for a new project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kit {
+	/// Minecraft datapack with `pack.mcmeta` and a load function.
+	Datapack,
+	/// Minecraft resourcepack with `pack.mcmeta` and a language file.
+	Resourcepack,
+}
+
+impl Kit {
+	pub const VARIANTS: &'static [&'static str] = &["datapack", "resourcepack"];
+
+	fn config(self) -> &'static str {
+		match self {
+			Self::Datapack => DATAPACK_CONFIG,
+			Self::Resourcepack => RESOURCEPACK_CONFIG,
+		}
+	}
+
+	fn files(self) -> &'static [KitFile] {
+		match self {
+			Self::Datapack => DATAPACK_FILES,
+			Self::Resourcepack => RESOURCEPACK_FILES,
+		}
+	}
+}
+
+impl FromStr for Kit {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"datapack" => Ok(Self::Datapack),
+			"resourcepack" => Ok(Self::Resourcepack),
+			_ => bail!(
+				"Unknown starter kit `{}`, expected `datapack` or `resourcepack`",
+				s
+			),
+		}
+	}
+}
+
+/// What `megu init` creates, the values left out are asked for or use their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+	pub dir: PathBuf,
+	/// Project name, the directory name by default.
+	pub name: Option<String>,
+	pub src: Option<String>,
+	pub output: Option<String>,
+	pub kit: Option<Kit>,
+	/// Ask for the source and output directories that weren't given instead of using the defaults.
+	pub interactive: bool,
+}
+
+/// Create `megu.toml`, the source directory and a `.gitignore` entry for the output directory in `options.dir`.
+///
+/// Files of the starter kit are rendered with megumax's own templating before being written. Nothing is written
+/// when one of them already exists.
+pub fn init_project(options: &InitOptions) -> Result<()> {
+	let name = match &options.name {
+		Some(name) => name.clone(),
+		None => default_name(&options.dir)?,
+	};
+	// Print `megu.toml` instead of `./megu.toml`.
+	let dir = match options.dir.as_path() {
+		dir if dir == Path::new(".") => Path::new(""),
+		dir => dir,
+	};
+	let src = value(
+		&options.src,
+		"Source directory",
+		DEFAULT_SRC,
+		options.interactive,
+	)?;
+	let output = value(
+		&options.output,
+		"Output directory",
+		DEFAULT_OUTPUT,
+		options.interactive,
+	)?;
+
+	if trim_dir(&src) == trim_dir(&output) {
+		bail!("The source and output directories can't both be `{}`", src);
+	}
+
+	let template = Template::default()
+		.insert("[version]".to_owned(), CURRENT_VERSION.to_string())
+		.insert("[src]".to_owned(), escape(&src))
+		.insert("[output]".to_owned(), escape(&output))
+		.insert("[name]".to_owned(), escape(&name))
+		.insert("[namespace]".to_owned(), namespace(&name));
+
+	let (config, files) = match options.kit {
+		Some(kit) => (kit.config(), kit.files()),
+		None => (DEFAULT_CONFIG, &[][..]),
+	};
+
+	let src_dir = dir.join(&src);
+	let mut outputs = vec![(dir.join(CONFIG_FILE), template.replace(config))];
+	outputs.extend(files.iter().map(|(path, content)| {
+		(
+			src_dir.join(template.replace(path)),
+			template.replace(content),
+		)
+	}));
+
+	if let Some((existing, _)) = outputs.iter().find(|(path, _)| path.exists()) {
+		bail!(
+			"`{}` already exists, nothing was written",
+			existing.display()
+		);
+	}
+
+	fs::create_dir_all(&src_dir)
+		.with_context(|| format!("Creating source directory `{}`", src_dir.display()))?;
+
+	for (path, content) in &outputs {
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)
+				.with_context(|| format!("Creating directory `{}`", parent.display()))?;
+		}
+
+		fs::write(path, content).with_context(|| format!("Writing `{}`", path.display()))?;
+		message::initialized(path);
+	}
+
+	if let Some(entry) = ignore_entry(&output) {
+		add_ignore_entry(&dir.join(".gitignore"), &entry)?;
+	}
+
+	message::init_summary(&name);
+	Ok(())
+}
+
+fn value(
+	given: &Option<String>,
+	question: &str,
+	default: &str,
+	interactive: bool,
+) -> Result<String> {
+	match given {
+		Some(value) => Ok(value.clone()),
+		None if interactive => ask(question, default),
+		None => Ok(default.to_owned()),
+	}
+}
+
+fn ask(question: &str, default: &str) -> Result<String> {
+	print!("{} [{}]: ", question, default);
+	io::stdout().flush()?;
+
+	let mut answer = String::new();
+	io::stdin()
+		.lock()
+		.read_line(&mut answer)
+		.with_context(|| "Reading answer from stdin")?;
+
+	match answer.trim() {
+		"" => Ok(default.to_owned()),
+		answer => Ok(answer.to_owned()),
+	}
+}
+
+fn default_name(dir: &Path) -> Result<String> {
+	let dir = crate::share::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+	let name = dir
+		.file_name()
+		.with_context(|| format!("Can't name a project in `{}`, use `--name`", dir.display()))?;
+	Ok(name.to_string_lossy().into_owned())
+}
+
+/// Minecraft namespaces only allow lowercase letters, digits, `_`, `-` and `.`.
+fn namespace(name: &str) -> String {
+	name.to_lowercase()
+		.chars()
+		.map(|c| match c {
+			'a'..='z' | '0'..='9' | '_' | '-' | '.' => c,
+			_ => '_',
+		})
+		.collect()
+}
+
+/// Escape `value` to be written inside a basic TOML string.
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn trim_dir(path: &str) -> &str {
+	path.trim_start_matches("./").trim_end_matches('/')
+}
+
+/// `.gitignore` entry matching the output directory, unless it's outside of the project.
+fn ignore_entry(output: &str) -> Option<String> {
+	let output = trim_dir(output);
+
+	if output.is_empty() || Path::new(output).is_absolute() || output.starts_with("..") {
+		return None;
+	}
+
+	Some(format!("/{}/", output))
+}
+
+fn add_ignore_entry(path: &Path, entry: &str) -> Result<()> {
+	let content = match fs::read_to_string(path) {
+		Ok(content) => content,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+		Err(e) => return Err(e).with_context(|| format!("Reading `{}`", path.display())),
+	};
+
+	let bare = entry.trim_matches('/');
+	let exists = content
+		.lines()
+		.any(|line| line.trim().trim_matches('/') == bare);
+
+	if exists {
+		return Ok(());
+	}
+
+	let mut content = content;
+	if !content.is_empty() && !content.ends_with('\n') {
+		content.push('\n');
+	}
+	content.push_str(entry);
+	content.push('\n');
+
+	fs::write(path, content).with_context(|| format!("Writing `{}`", path.display()))?;
+	message::initialized(path);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::app::report::SilentReporter;
+	use crate::app::{build_project, BuildOptions};
+	use crate::config::load_config;
+
+	#[test]
+	fn init_and_build_datapack() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		fs::write(root.join(".gitignore"), "target").unwrap();
+
+		let options = InitOptions {
+			dir: root.to_path_buf(),
+			name: Some("My Pack".into()),
+			kit: Some(Kit::Datapack),
+			..InitOptions::default()
+		};
+		init_project(&options).unwrap();
+
+		assert!(root
+			.join("src/data/my_pack/function/[color].mcfunction")
+			.is_file());
+		assert_eq!(
+			fs::read_to_string(root.join(".gitignore")).unwrap(),
+			"target\n/build/\n"
+		);
+
+		let config = load_config(root.join(CONFIG_FILE)).unwrap();
+		build_project(&config, &BuildOptions::default(), &mut SilentReporter).unwrap();
+
+		let meta = fs::read_to_string(root.join("build/pack.mcmeta")).unwrap();
+		assert!(meta.contains("\"My Pack datapack\""), "{}", meta);
+		let red =
+			fs::read_to_string(root.join("build/data/my_pack/function/red.mcfunction")).unwrap();
+		assert!(red.contains("says red"), "{}", red);
+
+		let err = init_project(&options).unwrap_err();
+		assert!(err.to_string().contains("already exists"), "{}", err);
+	}
+}
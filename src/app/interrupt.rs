@@ -0,0 +1,32 @@
+use crate::error::Interrupted;
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Stop at the next file on Ctrl-C instead of killing the process, a second Ctrl-C exits right away.
+pub fn install() -> Result<()> {
+	ctrlc::set_handler(|| {
+		if INTERRUPTED.swap(true, Ordering::SeqCst) {
+			std::process::exit(130);
+		}
+
+		log::warn!("Interrupted, stopping the build...");
+	})?;
+
+	Ok(())
+}
+
+/// Whether Ctrl-C was pressed.
+pub fn is_interrupted() -> bool {
+	INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fail once Ctrl-C was pressed.
+pub fn check() -> Result<()> {
+	if is_interrupted() {
+		return Err(Interrupted.into());
+	}
+
+	Ok(())
+}
@@ -0,0 +1,232 @@
+use super::{message, plan_project_keep_going, MessageFormat};
+use crate::config::Config;
+use crate::core::special::NTH_TEMPLATE;
+use crate::share::print_line;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// References to a placeholder in the content of the sources.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct References {
+	pub count: usize,
+	pub files: BTreeSet<PathBuf>,
+}
+
+impl References {
+	fn add(&mut self, file: &Path) {
+		self.count += 1;
+		self.files.insert(file.to_path_buf());
+	}
+}
+
+/// How the `[keys]` and `[template]` entries of a project are used by its sources.
+///
+/// Names are written without brackets.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Usage {
+	/// Every `[keys]` entry, including the ones that are never referenced.
+	pub keys: BTreeMap<String, References>,
+	pub unused_keys: Vec<String>,
+	/// `[template]` pools that no source path uses.
+	pub unused_pools: Vec<String>,
+	/// Placeholders in the content that are neither a key nor a pool of the source path.
+	pub undefined: BTreeMap<String, References>,
+	/// Sources whose outputs can't be computed, like a path using a pool that isn't defined, with the reason.
+	pub invalid_sources: BTreeMap<PathBuf, String>,
+}
+
+impl Usage {
+	/// Whether nothing was reported, references to keys aside.
+	pub fn is_clean(&self) -> bool {
+		self.unused_keys.is_empty()
+			&& self.unused_pools.is_empty()
+			&& self.undefined.is_empty()
+			&& self.invalid_sources.is_empty()
+	}
+}
+
+/// Scan the path and content of every source for placeholders.
+///
+/// Binary sources only count for the pools in their path. Numeric placeholders like the `[0]` of a JSON index
+/// are never reported as undefined.
+pub fn usage(config: &Config) -> Result<Usage> {
+	let (plan, errors) = plan_project_keep_going(config)?;
+	let pool = &config.template;
+
+	let mut keys: BTreeMap<_, _> = config
+		.keys
+		.iter()
+		.map(|(key, _)| (key.clone(), References::default()))
+		.collect();
+	let mut undefined = BTreeMap::<String, References>::new();
+	let mut used_pools = HashSet::new();
+
+	for planned in &plan.sources {
+		let file = &planned.link.from;
+		let relative = file.strip_prefix(&config.source).unwrap_or(file);
+		let path_pools: HashSet<_> = pool
+			.capture(&relative.to_string_lossy())
+			.into_iter()
+			.collect();
+
+		let content =
+			fs::read(file).with_context(|| format!("Reading source `{}`", file.display()))?;
+
+		if let Ok(content) = String::from_utf8(content) {
+			for placeholder in pool.capture(&content) {
+				if let Some(references) = keys.get_mut(&placeholder) {
+					references.add(file);
+				} else if !path_pools.contains(&placeholder)
+					&& placeholder != NTH_TEMPLATE
+					&& !is_index(&placeholder)
+				{
+					undefined.entry(placeholder).or_default().add(file);
+				}
+			}
+		}
+
+		used_pools.extend(path_pools);
+	}
+
+	let unused_keys = keys
+		.iter()
+		.filter(|(_, references)| references.count == 0)
+		.map(|(key, _)| unbracket(key))
+		.collect();
+	let mut unused_pools: Vec<_> = pool
+		.iter()
+		.filter(|(name, _)| !used_pools.contains(*name))
+		.map(|(name, _)| unbracket(name))
+		.collect();
+	unused_pools.sort();
+
+	Ok(Usage {
+		keys: keys.into_iter().map(|(k, v)| (unbracket(&k), v)).collect(),
+		unused_keys,
+		unused_pools,
+		undefined: undefined
+			.into_iter()
+			.map(|(k, v)| (unbracket(&k), v))
+			.collect(),
+		invalid_sources: errors
+			.into_iter()
+			.map(|source| (source.path, format!("{:#}", source.error)))
+			.collect(),
+	})
+}
+
+/// Print the key usage report of the project, returning whether it found nothing to report.
+pub fn lint_usage(config: &Config, format: MessageFormat) -> Result<bool> {
+	let usage = match format {
+		MessageFormat::Human => {
+			message::config_info(config);
+			let usage = usage(config)?;
+
+			for (key, references) in &usage.keys {
+				message::key_references(key, references);
+			}
+
+			if !usage.keys.is_empty() {
+				message::newline();
+			}
+
+			for key in &usage.unused_keys {
+				message::unused_key(key);
+			}
+
+			for pool in &usage.unused_pools {
+				message::unused_pool(pool);
+			}
+
+			for (placeholder, references) in &usage.undefined {
+				message::undefined_placeholder(placeholder, references);
+			}
+
+			for (path, error) in &usage.invalid_sources {
+				message::invalid_source(path, error);
+			}
+
+			message::usage_summary(&usage);
+			usage
+		}
+		MessageFormat::Json => {
+			let usage = usage(config)?;
+			print_line(&serde_json::to_string_pretty(&usage)?)?;
+			usage
+		}
+	};
+
+	Ok(usage.is_clean())
+}
+
+/// `[0]` and other numeric placeholders, which are far more likely to be an array index than a key.
+fn is_index(placeholder: &str) -> bool {
+	let name = unbracket(placeholder);
+	!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn unbracket(name: &str) -> String {
+	name.trim_start_matches('[')
+		.trim_end_matches(']')
+		.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+	use megumax_template::{Pool, Template};
+	use std::collections::HashMap;
+
+	#[test]
+	fn report_usage() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+
+		let mut pool = Pool::default_rule();
+		pool.insert("[color]".into(), vec!["red".into(), "blue".into()]);
+		pool.insert("[shape]".into(), vec!["circle".into()]);
+		let mut keys = HashMap::new();
+		keys.insert("[name]".to_owned(), "megu".to_owned());
+		keys.insert("[old]".to_owned(), "dead".to_owned());
+
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.with_template(pool)
+				.with_keys(Template::new(keys))
+				.build();
+
+		let src = &config.source;
+		fs::create_dir_all(src).unwrap();
+		fs::write(src.join("[color].txt"), "[name] [color] [nth] [typo]").unwrap();
+		fs::write(src.join("plain.txt"), "[name] [name] [color]").unwrap();
+		fs::write(
+			src.join("list.json"),
+			r#"{"values": [0], "first": values[0]}"#,
+		)
+		.unwrap();
+		fs::write(src.join("[typo].txt"), "").unwrap();
+
+		let usage = usage(&config).unwrap();
+
+		let name = &usage.keys["name"];
+		assert_eq!(name.count, 3);
+		assert_eq!(name.files.len(), 2);
+		assert_eq!(usage.unused_keys, vec!["old"]);
+		assert_eq!(usage.unused_pools, vec!["shape"]);
+
+		let undefined: Vec<_> = usage.undefined.keys().collect();
+		assert_eq!(undefined, vec!["color", "typo"]);
+		assert_eq!(
+			usage.undefined["color"].files,
+			vec![src.join("plain.txt")].into_iter().collect()
+		);
+
+		let invalid: Vec<_> = usage.invalid_sources.keys().collect();
+		assert_eq!(invalid, vec![&src.join("[typo].txt")]);
+		assert!(!usage.is_clean());
+	}
+}
@@ -1,19 +1,87 @@
-use crate::config::Config;
+use super::event::Summary;
+use super::lint::{References, Usage};
+use super::report::Timing;
+use crate::config::{Config, MergeStrategy};
+use crate::core::compare::Change;
 use crate::core::Link;
 use colorful::*;
 use megumax_template::Resource;
-use std::path::Path;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
 pub fn create(link: &Link) {
 	let path = format_path(&link.from);
 	log::info!("  Generate {}:", path.light_yellow());
 }
 
+pub fn unchanged(link: &Link) {
+	let path = format_path(&link.from);
+	log::info!("  Unchanged {}", path.dark_gray());
+}
+
 pub fn create_resource(resource: &Resource) {
 	let path = format_path(&resource.path);
 	log::info!("    {} {}", "✔".light_green(), path.blue());
 }
 
+pub fn copy_resource(resource: &Resource) {
+	let path = format_path(&resource.path);
+	log::info!(
+		"    {} {} {}",
+		"✔".light_green(),
+		path.blue(),
+		"(copied)".dark_gray()
+	);
+}
+
+pub fn merged(path: &Path) {
+	let path = format_path(path);
+	log::info!("  Merge {}", path.light_yellow());
+}
+
+pub fn planned_source(path: &Path) {
+	let path = format_path(path);
+	log::info!("  Plan {}:", path.light_yellow());
+}
+
+pub fn planned_output(
+	path: &Path,
+	variant: &BTreeMap<&str, &str>,
+	nth: usize,
+	merge: Option<MergeStrategy>,
+) {
+	let path = format_path(path);
+	let mut values: Vec<_> = variant
+		.iter()
+		.map(|(key, value)| format!("{} = {:?}", key, value))
+		.collect();
+	values.push(format!("nth = {}", nth));
+
+	let mut line = format!(
+		"    {} {} ({})",
+		"➜".light_green(),
+		path.blue(),
+		values.join(", ")
+	);
+
+	if let Some(merge) = merge {
+		line.push_str(&format!(" merged with `{}`", merge.name()));
+	}
+
+	log::info!("{}", line);
+}
+
+pub fn plan_summary(sources: usize, outputs: usize) {
+	log::info!(
+		"{} {} sources would produce {} outputs, nothing was written",
+		"Dry run:".light_red(),
+		sources,
+		outputs
+	);
+}
+
 pub fn config_info(config: &Config) {
 	let source = format_path(&config.source);
 	let dest = format_path(&config.dest);
@@ -24,16 +92,265 @@ pub fn config_info(config: &Config) {
 	log::info!("");
 }
 
+pub fn member(name: &str) {
+	log::info!("{} {}", "Member".light_red(), name.light_yellow());
+}
+
+pub fn workspace_summary(total: usize, failed: &[&str]) {
+	let built = total - failed.len();
+	log::info!("{}", "Workspace summary:".light_red());
+	log::info!("  {} {} built", "✔".light_green(), built);
+
+	if !failed.is_empty() {
+		log::info!("  {} {} failed", "✘".red(), failed.len());
+	}
+}
+
+/// Unified diff of `old` and `new` with three lines of context, `---` and `+++` headers give their names.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+	TextDiff::from_lines(old, new)
+		.unified_diff()
+		.context_radius(3)
+		.header(old_name, new_name)
+		.to_string()
+}
+
+pub fn removed(path: &Path, dry_run: bool) {
+	let path = format_path(path);
+	let label = if dry_run { "Would remove" } else { "Remove" };
+	log::info!("  {} {}", label.red(), path.light_yellow());
+}
+
+pub fn clean_summary(removed: usize, dry_run: bool) {
+	if dry_run {
+		log::info!(
+			"{} {} files would be removed",
+			"Dry run:".light_red(),
+			removed
+		);
+	} else {
+		log::info!("{} Removed {} files", "✔".light_green(), removed);
+	}
+}
+
+pub fn stale(change: &Change) {
+	let path = format_path(change.path());
+
+	let label = match change {
+		Change::Added { .. } => "Missing",
+		Change::Removed { .. } => "Extra",
+		Change::Modified { .. } => "Differs",
+	};
+
+	log::info!("  {} {:<7} {}", "✘".red(), label, path.light_yellow());
+}
+
+pub fn verify_summary(stale: usize) {
+	if stale == 0 {
+		log::info!("{} The output directory is up to date", "✔".light_green());
+	} else {
+		log::info!("");
+		log::info!(
+			"{} {} stale files, run `megu` to update the output directory",
+			"✘".red(),
+			stale
+		);
+	}
+}
+
+pub fn diff_summary(changes: usize) {
+	if changes == 0 {
+		log::info!("{} The output directory is up to date", "✔".light_green());
+	} else {
+		log::info!("{} {} files differ from a fresh build", "✘".red(), changes);
+	}
+}
+
+pub fn watching(config: &Config) {
+	let source = format_path(&config.source);
+	let path = format_path(&config.config_path);
+	log::info!(
+		"{} {} and {} for changes, press Ctrl-C to stop",
+		"Watching".light_red(),
+		source.blue(),
+		path.blue()
+	);
+}
+
+pub fn watch_change(path: &Path) {
+	let path = format_path(path);
+	log::info!("{} {}", "Changed".light_yellow(), path.blue());
+}
+
+pub fn watch_paused() {
+	log::info!(
+		"{} Fix the config to resume, sources are ignored until then",
+		"Paused:".light_red()
+	);
+}
+
+pub fn initialized(path: &Path) {
+	let path = format_path(path);
+	log::info!("  {} {}", "Create".light_green(), path.blue());
+}
+
+pub fn init_summary(name: &str) {
+	log::info!(
+		"{} Created project {}, run `megu` to build it",
+		"✔".light_green(),
+		name.light_yellow()
+	);
+}
+
+pub fn key_references(key: &str, references: &References) {
+	log::info!(
+		"  {} {} references in {} files",
+		key.light_yellow(),
+		references.count,
+		references.files.len()
+	);
+
+	for file in &references.files {
+		log::info!("    {}", format_path(file).blue());
+	}
+}
+
+pub fn unused_key(key: &str) {
+	log::info!(
+		"  {} Key {} is never used",
+		"⚠".yellow(),
+		key.light_yellow()
+	);
+}
+
+pub fn unused_pool(pool: &str) {
+	log::info!(
+		"  {} Pool {} isn't used in any source path",
+		"⚠".yellow(),
+		pool.light_yellow()
+	);
+}
+
+pub fn undefined_placeholder(placeholder: &str, references: &References) {
+	let files: Vec<_> = references
+		.files
+		.iter()
+		.map(|file| format_path(file))
+		.collect();
+	log::info!(
+		"  {} [{}] is neither a key nor a pool of the path, in {}",
+		"✘".red(),
+		placeholder.light_yellow(),
+		files.join(", ")
+	);
+}
+
+pub fn invalid_source(path: &Path, error: &str) {
+	log::info!(
+		"  {} {} can't be planned: {}",
+		"✘".red(),
+		format_path(path).light_yellow(),
+		error
+	);
+}
+
+pub fn usage_summary(usage: &Usage) {
+	if usage.is_clean() {
+		log::info!("{} Every key and pool is used", "✔".light_green());
+	} else {
+		log::info!("");
+		log::info!(
+			"{} {} unused keys, {} unused pools, {} undefined placeholders, {} invalid sources",
+			"✘".red(),
+			usage.unused_keys.len(),
+			usage.unused_pools.len(),
+			usage.undefined.len(),
+			usage.invalid_sources.len()
+		);
+	}
+}
+
+pub fn build_summary(summary: &Summary) {
+	log::info!(
+		"{} Rendered {} files and copied {} binary files from {} sources ({} unchanged), wrote {} in {}",
+		"✔".light_green(),
+		summary.resources - summary.binaries,
+		summary.binaries,
+		summary.sources,
+		summary.unchanged,
+		format_bytes(summary.bytes),
+		format_duration(Duration::from_millis(summary.elapsed_ms))
+	);
+}
+
+/// List the time spent on each of `sources`, which are sorted slowest first.
+pub fn timings(sources: &[(PathBuf, Timing)], all: bool) {
+	if sources.is_empty() {
+		return;
+	}
+
+	let title = if all { "Timings:" } else { "Slowest sources:" };
+	log::info!("  {}", title.light_red());
+
+	for (path, timing) in sources {
+		log::info!(
+			"    {:>8} {} ({} outputs, {})",
+			format_duration(timing.elapsed),
+			format_path(path).light_yellow(),
+			timing.outputs,
+			format_bytes(timing.bytes)
+		);
+	}
+}
+
+fn format_bytes(bytes: u64) -> String {
+	const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+
+	if bytes < 1024 {
+		return format!("{} B", bytes);
+	}
+
+	let mut size = bytes as f64 / 1024.0;
+	let mut unit = UNITS[0];
+
+	for next in &UNITS[1..] {
+		if size < 1024.0 {
+			break;
+		}
+		size /= 1024.0;
+		unit = next;
+	}
+
+	format!("{:.1} {}", size, unit)
+}
+
+fn format_duration(duration: Duration) -> String {
+	if duration.as_secs() > 0 {
+		format!("{:.2}s", duration.as_secs_f64())
+	} else if duration.as_millis() > 0 {
+		format!("{}ms", duration.as_millis())
+	} else {
+		format!("{}µs", duration.as_micros())
+	}
+}
+
 pub fn newline() {
 	log::info!("");
 }
 
 fn format_path(path: &Path) -> String {
-	let components: Vec<String> = path
-		.components()
-		.map(|s| s.as_os_str())
-		.map(|s| s.to_string_lossy())
-		.map(|s| s.to_string())
-		.collect();
-	components.join("/")
+	let mut result = String::new();
+
+	for component in path.components() {
+		if !result.is_empty() && !result.ends_with('/') {
+			result.push('/');
+		}
+
+		match component {
+			Component::RootDir => result.push('/'),
+			component => result.push_str(&component.as_os_str().to_string_lossy()),
+		}
+	}
+
+	result
 }
@@ -0,0 +1,106 @@
+use super::message;
+use crate::config::read_from_path;
+use crate::toml::migrate::{migrate, Migrated};
+use crate::workspace::Workspace;
+use anyhow::{Context, Result};
+use colorful::*;
+use std::path::{Path, PathBuf};
+
+/// Config files to upgrade: the one at `path` and, at a workspace root, the ones of the members picked by `packages`.
+pub fn config_files(path: &Path, packages: &[String]) -> Result<Vec<PathBuf>> {
+	let mut files = vec![path.to_path_buf()];
+
+	if let Some(workspace) = Workspace::load(path)? {
+		let members = workspace.select(packages)?;
+		files.extend(
+			members
+				.into_iter()
+				.map(|member| member.config_path.clone())
+				.filter(|member| member != path),
+		);
+	}
+
+	Ok(files)
+}
+
+/// Upgrade the config file at `path` in place, showing the diff before writing it.
+pub fn migrate_config(path: &Path, dry_run: bool) -> Result<Migrated> {
+	let content = read_from_path(path)?;
+	let migrated =
+		migrate(&content).with_context(|| format!("Migrating config file `{}`", path.display()))?;
+
+	if !migrated.is_changed() {
+		log::info!("{} is already at version {}", path.display(), migrated.to);
+		return Ok(migrated);
+	}
+
+	log::info!(
+		"{} {} from version {} to {}:",
+		"Migrating".light_red(),
+		path.display(),
+		migrated.from,
+		migrated.to
+	);
+
+	for step in &migrated.steps {
+		log::info!("  {} {}", "✔".light_green(), step);
+	}
+
+	log::info!("");
+	let name = path.display().to_string();
+	log::info!(
+		"{}",
+		message::unified_diff(&content, &migrated.content, &name, &name)
+	);
+
+	if !dry_run {
+		std::fs::write(path, &migrated.content)
+			.with_context(|| format!("Writing config file `{}`", path.display()))?;
+	}
+
+	Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+
+	#[test]
+	fn migrate_in_place() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("megu.toml");
+		let content = "[build]\noutput = \"out\"\n";
+		fs::write(&path, content).unwrap();
+
+		let migrated = migrate_config(&path, true).unwrap();
+		assert!(migrated.is_changed());
+		assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+		migrate_config(&path, false).unwrap();
+		assert_eq!(fs::read_to_string(&path).unwrap(), migrated.content);
+		assert!(!migrate_config(&path, false).unwrap().is_changed());
+	}
+
+	#[test]
+	fn include_workspace_members() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().join("megu.toml");
+		let member = dir.path().join("pack").join("megu.toml");
+		fs::create_dir_all(member.parent().unwrap()).unwrap();
+		fs::write(&member, "[build]\n").unwrap();
+		fs::write(&root, "[workspace]\nmembers = [\"pack\"]\n").unwrap();
+
+		assert_eq!(
+			config_files(&root, &[]).unwrap(),
+			vec![root, member.clone()]
+		);
+		assert_eq!(config_files(&member, &[]).unwrap(), vec![member]);
+	}
+
+	#[test]
+	fn missing_config() {
+		let dir = tempfile::tempdir().unwrap();
+		assert!(migrate_config(&dir.path().join("megu.toml"), false).is_err());
+	}
+}
@@ -1,60 +1,280 @@
-use super::{check_expression_block, message};
-use crate::config::Config;
-use crate::core::{Link, Walker};
+use super::event::Summary;
+use super::report::{Reporter, Timing};
+use super::{check_expression_block, interrupt, BuildOptions, Failures, MessageFormat, Rendered};
+use crate::config::{CleanMode, Config};
+use crate::core::manifest::{hash_bytes, output_key, Manifest, SourceEntry};
+use crate::core::merge::Merges;
+use crate::core::plan::Plan;
+use crate::core::staging::Staging;
+use crate::core::Walker;
+use crate::error::SourceError;
+use crate::share::{is_unchanged, read_file, write_file};
 use crate::utils::StringStream;
 use anyhow::Result;
 use futures::prelude::*;
 use megumax_template::{Resource, Template};
-use smol::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use smol::io::{AsyncRead, AsyncWrite};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-pub fn build_project(config: &Config) -> Result<()> {
-	smol::block_on(async { build_project_inner(config).await })
+pub fn build_project(
+	config: &Config,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Result<()> {
+	smol::block_on(async { build_project_inner(config, options, reporter).await })
 }
 
-async fn build_project_inner(config: &Config) -> Result<()> {
-	message::config_info(config);
+/// Walk the source directory and expand every file into the resources it produces.
+pub fn plan_project(config: &Config) -> Result<Plan> {
+	smol::block_on(plan_sources(config, &mut Failures::new(false)))
+}
+
+/// Like [`plan_project`], but the sources that can't be planned are returned with their errors instead of failing.
+pub fn plan_project_keep_going(config: &Config) -> Result<(Plan, Vec<SourceError>)> {
+	let mut failures = Failures::new(true);
+	let plan = smol::block_on(plan_sources(config, &mut failures))?;
+	Ok((plan, failures.errors))
+}
 
-	let mut files = Walker::from_config(&config);
-	config.clear_build_dir()?;
+async fn plan_sources(config: &Config, failures: &mut Failures) -> Result<Plan> {
+	let mut files = Walker::from_config(config);
+	let mut plan = Plan::new();
 
 	while let Some(link) = files.next().await {
 		let link = link?;
+		let from = link.from.clone();
+		failures.record(&from, plan.push(link, &config.template))?;
+	}
+
+	Ok(plan)
+}
+
+async fn build_project_inner(
+	config: &Config,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Result<()> {
+	let started = Instant::now();
+
+	// A JSON dry run prints the plan as a single document instead.
+	if !(options.dry_run && options.message_format == MessageFormat::Json) {
+		reporter.config_info(config);
+	}
+
+	let mut failures = Failures::new(options.keep_going);
+	let plan = plan_sources(config, &mut failures).await?;
+
+	if options.dry_run {
+		reporter.plan(config, &plan)?;
+		plan.check_collisions(config)?;
+		return failures.finish();
+	}
+
+	// Every output is known before touching the output directory, so a collision leaves it untouched.
+	plan.check_collisions(config)?;
+	config.check_dest()?;
+
+	let previous = Manifest::load(config);
+
+	// Without a manifest there is no way to tell which files are ours, so everything else gets removed.
+	if previous.is_none() && config.clean == CleanMode::All {
+		config.check_build_dir(options.force)?;
+	}
+
+	let mut staging = Staging::new(config)?;
+	let mut manifest = Manifest::new(config);
+	let mut merges = Merges::new(plan.merge_targets(config));
+	let mut summary = Summary {
+		sources: plan.sources.len(),
+		..Summary::default()
+	};
+	reporter.planned(plan.sources.len(), plan.resource_count());
+
+	for planned in plan.sources {
+		interrupt::check()?;
+		let link = planned.link;
+
+		// A merged output depends on every source contributing to it, so those are always rendered.
+		let merging = planned.resources.iter().any(|r| merges.contains(&r.path));
+
+		// Sources the caller knows are unchanged aren't even read.
+		if let Some(entry) = previous
+			.as_ref()
+			.filter(|_| !merging && !options.is_changed(config, &link))
+			.and_then(|m| m.cached(config, &link))
+		{
+			manifest.insert(config, &link, entry.clone());
+			summary.unchanged += 1;
+			continue;
+		}
+
+		let source_started = Instant::now();
+		let content = match failures.record(&link.from, read_file(&link.from).await)? {
+			Some(content) => content,
+			None => continue,
+		};
+		let hash = hash_bytes(&content);
+
+		if let Some(entry) = previous
+			.as_ref()
+			.filter(|_| !merging)
+			.and_then(|m| m.unchanged(config, &link, &hash))
+		{
+			reporter.link_unchanged(&link);
+			manifest.insert(config, &link, entry.clone());
+			summary.unchanged += 1;
+			continue;
+		}
+
+		reporter.link_start(&link);
+		summary.rendered += 1;
+		let mut entry = SourceEntry::new(hash);
+		let mut timing = Timing::default();
 
-		message::create(&link);
-		let resources = link.to_resources(&config.template)?;
-		let mut streams = stream::iter(resources).map(|x| create(x, &link, &config.keys));
+		let (content, keys) = (&content, &config.keys);
+		let resources = planned.resources.into_iter().enumerate();
+		let mut streams = stream::iter(resources).map(|(nth, resource)| async move {
+			let output = render(&resource, content, keys).await;
+			(nth, resource, output)
+		});
 
-		while let Some(resource) = streams.next().await {
-			let resource = resource.await?;
-			message::create_resource(&resource);
+		while let Some(rendered) = streams.next().await {
+			let (nth, resource, rendered) = rendered.await;
+			let Rendered {
+				content: output,
+				binary,
+			} = rendered;
+			let key = output_key(config, &resource.path);
+
+			if merges.contains(&resource.path) {
+				// The hash is only known once every part is merged.
+				merges.add(&resource.path, &link.from, nth, output);
+				entry.outputs.insert(key, String::new());
+			} else {
+				let staged = stage(&mut staging, &resource.path, &output).await;
+
+				match failures.record(&link.from, staged)? {
+					Some(true) => timing.bytes += output.len() as u64,
+					Some(false) => (),
+					None => break,
+				}
+
+				entry.outputs.insert(key, hash_bytes(&output));
+			}
+
+			timing.outputs += 1;
+			summary.resources += 1;
+			summary.binaries += binary as usize;
+
+			if binary {
+				reporter.binary_copied(&link, &resource, nth);
+			} else {
+				reporter.resource_created(&link, &resource, nth);
+			}
 		}
 
-		message::newline();
+		manifest.insert(config, &link, entry);
+		timing.elapsed = source_started.elapsed();
+		summary.bytes += timing.bytes;
+		reporter.link_finished(&link, &timing);
 	}
 
+	// Merged outputs would miss the parts of failing sources.
+	failures.finish()?;
+
+	for (path, content) in merges.finish()? {
+		if stage(&mut staging, &path, &content).await? {
+			summary.bytes += content.len() as u64;
+		}
+
+		let key = output_key(config, &path);
+		manifest.update_output(&key, &hash_bytes(&content));
+		summary.merged += 1;
+
+		reporter.merged(&path, config.merge_strategy(&key));
+	}
+
+	interrupt::check()?;
+
+	// Nothing in the output directory changed until now, so any failure above leaves the previous build intact. The
+	// staging directory then takes its place as a whole. When the files can only be moved one at a time, the previous
+	// manifest stays in place until it's done, so the next build renders again any output a failure left behind.
+	config.mark_build_dir()?;
+	staging.commit()?;
+	manifest.clean(previous.as_ref(), config)?;
+	manifest.stamp(config);
+	manifest.save(config)?;
+
+	summary.elapsed_ms = started.elapsed().as_millis() as u64;
+	reporter.finished(&summary);
+
 	Ok(())
 }
 
-async fn create(resource: Resource, link: &Link, keys: &Template) -> Result<Resource> {
-	let link = link.with_resource(&resource);
+/// Render every output of `plan` in memory, keyed by its path, without touching the output directory.
+pub fn render_project(config: &Config, plan: Plan) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+	smol::block_on(async {
+		let mut merges = Merges::new(plan.merge_targets(config));
+		let mut outputs = BTreeMap::new();
+
+		for planned in plan.sources {
+			interrupt::check()?;
+			let content = read_file(&planned.link.from).await?;
 
-	let reader = BufReader::new(link.read().await?);
-	let writer = BufWriter::new(link.create().await?);
+			for (nth, resource) in planned.resources.into_iter().enumerate() {
+				let output = render(&resource, &content, &config.keys).await.content;
+
+				if merges.contains(&resource.path) {
+					merges.add(&resource.path, &planned.link.from, nth, output);
+				} else {
+					outputs.insert(resource.path, output);
+				}
+			}
+		}
 
+		outputs.extend(merges.finish()?);
+		Ok(outputs)
+	})
+}
+
+/// Render a single resource outside of a build.
+pub fn render_resource(resource: &Resource, content: &[u8], keys: &Template) -> Vec<u8> {
+	smol::block_on(render(resource, content, keys)).content
+}
+
+/// Write `content` into the staging directory, unless the output at `path` already has exactly that content.
+///
+/// Returns whether it was written.
+async fn stage(staging: &mut Staging, path: &Path, content: &[u8]) -> Result<bool> {
+	if is_unchanged(path, content).await {
+		return Ok(false);
+	}
+
+	write_file(staging.stage(path), content).await?;
+	Ok(true)
+}
+
+/// Render a single resource, binary files are copied as they are.
+async fn render(resource: &Resource, content: &[u8], keys: &Template) -> Rendered {
 	let apply_template = |content: String| {
 		let content = resource.replace(&content);
 		keys.replace(&content)
 	};
 
-	let result = generate_text(reader, writer, apply_template, check_expression_block).await;
+	let mut output = Vec::new();
+	let result = generate_text(content, &mut output, apply_template, check_expression_block).await;
 
 	// Returning error mean the file is a binary file and we just have to copy its content and not replacing anything.
 	if result.is_err() {
-		smol::fs::remove_file(&link.to).await?;
-		smol::fs::copy(&link.from, &link.to).await?;
+		output = content.to_vec();
 	}
 
-	Ok(resource)
+	Rendered {
+		content: output,
+		binary: result.is_err(),
+	}
 }
 
 /// Read string from the Reader, transform it using `f` function and write it into the Writer.
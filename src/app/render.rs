@@ -0,0 +1,161 @@
+use super::render_resource;
+use crate::config::Config;
+use crate::core::special::NTH_TEMPLATE;
+use crate::share::stringify_path;
+use anyhow::{bail, Context, Result};
+use megumax_template::{Resource, Template};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Parse a `NAME=VALUE` variant value from the command line.
+pub fn parse_var(s: &str) -> Result<(String, String)> {
+	let (name, value) = s
+		.split_once('=')
+		.with_context(|| format!("Expected `NAME=VALUE`, found `{}`", s))?;
+	Ok((name.trim().to_owned(), value.to_owned()))
+}
+
+/// Render the source at `file`, or stdin when `file` is `-`, into `writer`.
+///
+/// Pools used in the path of `file` pick the variant, the ones with several values must be chosen with `vars`.
+/// Every other entry of `vars` is replaced in the content as well.
+pub fn render_file<W: Write>(
+	config: &Config,
+	file: &Path,
+	vars: &[(String, String)],
+	mut writer: W,
+) -> Result<()> {
+	let vars: Vec<_> = vars.iter().map(|(k, v)| (bracket(k), v.clone())).collect();
+
+	let (mut resource, content) = if file == Path::new("-") {
+		let mut content = Vec::new();
+		std::io::stdin()
+			.read_to_end(&mut content)
+			.with_context(|| "Reading template from stdin")?;
+
+		let template = Template::default().insert(NTH_TEMPLATE.to_owned(), "0".to_owned());
+		(Resource::new(PathBuf::from("-"), template), content)
+	} else {
+		let content = std::fs::read(file)
+			.with_context(|| format!("Reading template `{}`", file.display()))?;
+		(select_variant(config, file, &vars)?, content)
+	};
+
+	for (key, value) in vars {
+		resource.template.set(key, value);
+	}
+
+	let output = render_resource(&resource, &content, &config.keys);
+	let result = writer.write_all(&output).and_then(|_| writer.flush());
+
+	match result {
+		// The reader went away, like `head` after it has enough lines.
+		Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+		result => result.with_context(|| "Writing rendered template"),
+	}
+}
+
+/// Find the single variant of `file` matching every value in `vars`.
+fn select_variant(config: &Config, file: &Path, vars: &[(String, String)]) -> Result<Resource> {
+	let path = stringify_path(&file)?;
+	let resources = config
+		.template
+		.template_resources(path)
+		.with_context(|| format!("Looking up keyword in `{}`", path))?;
+
+	let mut matches: Vec<_> = resources
+		.enumerate()
+		.map(|(n, mut resource)| {
+			resource
+				.template
+				.set(NTH_TEMPLATE.to_owned(), n.to_string());
+			resource
+		})
+		.filter(|resource| {
+			vars.iter()
+				.all(|(key, value)| match resource.template.get(key) {
+					Some(found) => found == value,
+					None => true,
+				})
+		})
+		.collect();
+
+	match matches.len() {
+		1 => Ok(matches.remove(0)),
+		0 => bail!(
+			"No variant of `{}` matches the given `--var` values",
+			file.display()
+		),
+		_ => {
+			let ambiguous: Vec<_> = config
+				.template
+				.capture(path)
+				.into_iter()
+				.filter(|key| !vars.iter().any(|(name, _)| name == key))
+				.filter(|key| config.template.get(key).map_or(0, <[String]>::len) > 1)
+				.map(|key| {
+					let values = config.template.get(&key).unwrap_or_default().join(", ");
+					format!("`{}` ({})", key, values)
+				})
+				.collect();
+
+			bail!(
+				"`{}` has {} variants, pick one with `--var` for {}",
+				file.display(),
+				matches.len(),
+				ambiguous.join(", ")
+			)
+		}
+	}
+}
+
+/// Accept pool names both with and without brackets.
+fn bracket(name: &str) -> String {
+	if name.starts_with('[') && name.ends_with(']') {
+		name.to_owned()
+	} else {
+		format!("[{}]", name)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+	use megumax_template::Pool;
+	use std::collections::HashMap;
+	use std::fs;
+
+	fn config(root: &Path) -> Config {
+		let mut pool = Pool::default_rule();
+		pool.insert("[color]".into(), vec!["red".into(), "blue".into()]);
+		let mut keys = HashMap::new();
+		keys.insert("[name]".to_owned(), "megu".to_owned());
+
+		ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+			.with_template(pool)
+			.with_keys(Template::new(keys))
+			.build()
+	}
+
+	#[test]
+	fn render_selected_variant() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root);
+		let file = root.join("src").join("[color].txt");
+		fs::create_dir_all(file.parent().unwrap()).unwrap();
+		fs::write(&file, "[name] is [color] ([nth])").unwrap();
+
+		let vars = vec![parse_var("color=blue").unwrap()];
+		let mut output = Vec::new();
+		render_file(&config, &file, &vars, &mut output).unwrap();
+		assert_eq!(String::from_utf8(output).unwrap(), "megu is blue (1)");
+
+		let error = render_file(&config, &file, &[], Vec::new()).unwrap_err();
+		assert!(error.to_string().contains("`[color]` (red, blue)"));
+
+		let vars = vec![parse_var("color=green").unwrap()];
+		assert!(render_file(&config, &file, &vars, Vec::new()).is_err());
+	}
+}
@@ -0,0 +1,494 @@
+use super::event::{self, Event, Output, SourceStatus, Summary};
+use super::{dry_run, message, MessageFormat};
+use crate::config::{Config, MergeStrategy};
+use crate::core::plan::Plan;
+use crate::core::special::variant;
+use crate::core::Link;
+use anyhow::Result;
+use colorful::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use megumax_template::Resource;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Receives the progress of a build, so it can be printed or collected.
+///
+/// Only the callbacks a build can't do without are required, the others do nothing by default.
+pub trait Reporter {
+	/// A build of the project described by `config` started.
+	fn config_info(&mut self, config: &Config);
+
+	/// A dry run planned `plan`, nothing is built afterwards.
+	fn plan(&mut self, _config: &Config, _plan: &Plan) -> Result<()> {
+		Ok(())
+	}
+
+	/// The build will go through `sources` source files, producing `resources` outputs.
+	fn planned(&mut self, _sources: usize, _resources: usize) {}
+
+	/// `link` is about to be rendered.
+	fn link_start(&mut self, link: &Link);
+
+	/// `link` didn't change since the previous build, so its outputs were kept.
+	fn link_unchanged(&mut self, _link: &Link) {}
+
+	/// Every output of `link` was rendered.
+	fn link_finished(&mut self, _link: &Link, _timing: &Timing) {}
+
+	/// `resource`, the `nth` variant of `link`, was rendered.
+	fn resource_created(&mut self, link: &Link, resource: &Resource, nth: usize);
+
+	/// `resource`, the `nth` variant of `link`, was copied as is because its source isn't text.
+	fn binary_copied(&mut self, link: &Link, resource: &Resource, nth: usize) {
+		self.resource_created(link, resource, nth);
+	}
+
+	/// Several outputs were combined into the one at `path`.
+	fn merged(&mut self, _path: &Path, _strategy: MergeStrategy) {}
+
+	/// The build succeeded.
+	fn finished(&mut self, _summary: &Summary) {}
+
+	/// Watch mode is waiting for changes to the sources of `config` or to the config file.
+	fn watching(&mut self, _config: &Config) {}
+
+	/// Watch mode noticed a change to `path`, which is rebuilt next.
+	fn changed(&mut self, _path: &Path) {}
+
+	/// Watch mode failed to load the config, sources are ignored until it's fixed.
+	fn paused(&mut self) {}
+
+	/// A workspace member named `name` is about to be built.
+	fn member(&mut self, _name: &str) {}
+
+	/// Every one of the `total` workspace members was built, the ones named in `failed` didn't succeed.
+	fn workspace_finished(&mut self, _total: usize, _failed: &[&str]) {}
+
+	/// The command failed with `error`.
+	///
+	/// Builds never call it themselves, the error is returned instead, so the caller of [`build_project`] or
+	/// [`build_workspace`] reports it once it's done with it.
+	///
+	/// [`build_project`]: super::build_project
+	/// [`build_workspace`]: super::workspace::build_workspace
+	fn error(&mut self, error: &anyhow::Error);
+}
+
+/// Time spent on a single source and what it produced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timing {
+	pub elapsed: Duration,
+	pub outputs: usize,
+	/// Size of the outputs written, the ones that already had the same content aren't counted.
+	pub bytes: u64,
+}
+
+/// The progress bar being drawn, if any, so text written to stderr can hide it first.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Run `f` with the progress bar hidden, so the text it writes to stderr doesn't tear the bar.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+	let bar = BAR.lock().map(|bar| bar.clone()).unwrap_or(None);
+
+	match bar {
+		Some(bar) => bar.suspend(f),
+		None => f(),
+	}
+}
+
+/// Return the reporter printing messages in `format`, with a progress bar when stderr is a terminal.
+pub fn for_format(format: MessageFormat, quiet: bool, timings: bool) -> Box<dyn Reporter> {
+	match format {
+		MessageFormat::Human => Box::new(
+			TerminalReporter::new()
+				.with_progress(!quiet && io::stderr().is_terminal())
+				.with_timings(timings),
+		),
+		MessageFormat::Json => Box::new(JsonReporter),
+	}
+}
+
+/// Colored text for people, written through the logger.
+///
+/// With a progress bar, the per-file messages are replaced by the bar and only the summary is printed. Other log
+/// messages should be written through [`suspend`] while the bar is drawn.
+#[derive(Debug, Default)]
+pub struct TerminalReporter {
+	progress: bool,
+	/// Print the time spent on every source instead of only the slowest ones.
+	timings: bool,
+	bar: Option<ProgressBar>,
+	sources: Vec<(PathBuf, Timing)>,
+}
+
+impl TerminalReporter {
+	/// Number of sources listed in the summary when `timings` is off.
+	const SLOWEST: usize = 5;
+
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_progress(mut self, progress: bool) -> Self {
+		self.progress = progress;
+		self
+	}
+
+	pub fn with_timings(mut self, timings: bool) -> Self {
+		self.timings = timings;
+		self
+	}
+
+	fn show_bar(&mut self, bar: ProgressBar) {
+		if let Ok(mut shown) = BAR.lock() {
+			*shown = Some(bar.clone());
+		}
+		self.bar = Some(bar);
+	}
+
+	fn take_bar(&mut self) -> Option<ProgressBar> {
+		if let Ok(mut shown) = BAR.lock() {
+			*shown = None;
+		}
+		self.bar.take()
+	}
+
+	fn advance(&self) {
+		if let Some(bar) = &self.bar {
+			bar.inc(1);
+		}
+	}
+}
+
+impl Reporter for TerminalReporter {
+	fn config_info(&mut self, config: &Config) {
+		self.sources.clear();
+		message::config_info(config);
+	}
+
+	fn plan(&mut self, config: &Config, plan: &Plan) -> Result<()> {
+		dry_run::print_plan(config, plan, MessageFormat::Human)
+	}
+
+	fn planned(&mut self, sources: usize, _resources: usize) {
+		if !self.progress {
+			return;
+		}
+
+		let style = ProgressStyle::with_template(
+			"{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} sources, {msg} ({elapsed})",
+		)
+		.unwrap_or_else(|_| ProgressStyle::default_bar())
+		.progress_chars("=> ");
+
+		let bar = ProgressBar::new(sources as u64).with_style(style);
+		bar.set_message("0 variants");
+		self.show_bar(bar);
+	}
+
+	fn link_start(&mut self, link: &Link) {
+		if self.bar.is_none() {
+			message::create(link);
+		}
+	}
+
+	fn link_unchanged(&mut self, link: &Link) {
+		match &self.bar {
+			Some(_) => self.advance(),
+			None => message::unchanged(link),
+		}
+	}
+
+	fn link_finished(&mut self, link: &Link, timing: &Timing) {
+		self.sources.push((link.from.clone(), timing.clone()));
+
+		match &self.bar {
+			Some(bar) => {
+				let variants: usize = self.sources.iter().map(|(_, t)| t.outputs).sum();
+				bar.set_message(format!("{} variants", variants));
+				bar.inc(1);
+			}
+			None => message::newline(),
+		}
+	}
+
+	fn resource_created(&mut self, _link: &Link, resource: &Resource, _nth: usize) {
+		if self.bar.is_none() {
+			message::create_resource(resource);
+		}
+	}
+
+	fn binary_copied(&mut self, _link: &Link, resource: &Resource, _nth: usize) {
+		if self.bar.is_none() {
+			message::copy_resource(resource);
+		}
+	}
+
+	fn merged(&mut self, path: &Path, _strategy: MergeStrategy) {
+		if self.bar.is_none() {
+			message::merged(path);
+		}
+	}
+
+	fn finished(&mut self, summary: &Summary) {
+		if let Some(bar) = self.take_bar() {
+			bar.finish_and_clear();
+		}
+
+		// Slowest first, ties in path order so the output is stable.
+		self.sources
+			.sort_by(|(a, x), (b, y)| y.elapsed.cmp(&x.elapsed).then_with(|| a.cmp(b)));
+		let shown = if self.timings {
+			self.sources.len()
+		} else {
+			Self::SLOWEST
+		};
+
+		message::build_summary(summary);
+		message::timings(&self.sources[..shown.min(self.sources.len())], self.timings);
+	}
+
+	fn watching(&mut self, config: &Config) {
+		message::watching(config);
+	}
+
+	fn changed(&mut self, path: &Path) {
+		message::watch_change(path);
+	}
+
+	fn paused(&mut self) {
+		message::watch_paused();
+	}
+
+	fn member(&mut self, name: &str) {
+		// The bar of a member that failed is never finished.
+		if let Some(bar) = self.take_bar() {
+			bar.abandon();
+		}
+
+		message::member(name);
+	}
+
+	fn workspace_finished(&mut self, total: usize, failed: &[&str]) {
+		message::workspace_summary(total, failed);
+	}
+
+	fn error(&mut self, error: &anyhow::Error) {
+		if let Some(bar) = self.take_bar() {
+			bar.abandon();
+		}
+
+		// Not through the logger, so errors still show with `--quiet`.
+		eprintln!("{} {:#}", "⚠".red(), error);
+	}
+}
+
+/// Newline-delimited JSON events on stdout, see [`event`] for the schema.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonReporter;
+
+impl JsonReporter {
+	fn output<'a>(link: &'a Link, resource: &'a Resource, nth: usize) -> Output<'a> {
+		Output {
+			source: &link.from,
+			path: &resource.path,
+			nth,
+			variant: variant(resource),
+		}
+	}
+}
+
+impl Reporter for JsonReporter {
+	fn config_info(&mut self, config: &Config) {
+		event::emit(&Event::BuildStarted {
+			config: &config.config_path,
+			source: &config.source,
+			dest: &config.dest,
+		});
+	}
+
+	fn link_start(&mut self, link: &Link) {
+		event::emit(&Event::Source {
+			path: &link.from,
+			status: SourceStatus::Rendered,
+		});
+	}
+
+	fn link_unchanged(&mut self, link: &Link) {
+		event::emit(&Event::Source {
+			path: &link.from,
+			status: SourceStatus::Unchanged,
+		});
+	}
+
+	fn resource_created(&mut self, link: &Link, resource: &Resource, nth: usize) {
+		event::emit(&Event::Resource(Self::output(link, resource, nth)));
+	}
+
+	fn binary_copied(&mut self, link: &Link, resource: &Resource, nth: usize) {
+		event::emit(&Event::Binary(Self::output(link, resource, nth)));
+	}
+
+	fn merged(&mut self, path: &Path, strategy: MergeStrategy) {
+		event::emit(&Event::Merged { path, strategy });
+	}
+
+	fn finished(&mut self, summary: &Summary) {
+		event::emit(&Event::BuildFinished(summary.clone()));
+	}
+
+	fn plan(&mut self, config: &Config, plan: &Plan) -> Result<()> {
+		dry_run::print_plan(config, plan, MessageFormat::Json)
+	}
+
+	fn watching(&mut self, config: &Config) {
+		event::emit(&Event::Watching {
+			config: &config.config_path,
+			source: &config.source,
+		});
+	}
+
+	fn changed(&mut self, path: &Path) {
+		event::emit(&Event::Changed { path });
+	}
+
+	fn paused(&mut self) {
+		event::emit(&Event::Paused);
+	}
+
+	fn member(&mut self, name: &str) {
+		event::emit(&Event::Member { name });
+	}
+
+	fn workspace_finished(&mut self, total: usize, failed: &[&str]) {
+		event::emit(&Event::WorkspaceFinished { total, failed });
+	}
+
+	fn error(&mut self, error: &anyhow::Error) {
+		event::emit(&Event::Error {
+			message: format!("{:#}", error),
+		});
+	}
+}
+
+/// Prints nothing, for library users that only care about the result.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilentReporter;
+
+impl Reporter for SilentReporter {
+	fn config_info(&mut self, _config: &Config) {}
+
+	fn link_start(&mut self, _link: &Link) {}
+
+	fn resource_created(&mut self, _link: &Link, _resource: &Resource, _nth: usize) {}
+
+	fn error(&mut self, _error: &anyhow::Error) {}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::app::{build_project, BuildOptions};
+	use crate::config::ConfigBuilder;
+	use std::fs;
+
+	/// Records the callbacks of a build as short strings.
+	#[derive(Default)]
+	struct Recorder(Vec<String>);
+
+	impl Reporter for Recorder {
+		fn config_info(&mut self, _config: &Config) {
+			self.0.push("config".into());
+		}
+
+		fn link_start(&mut self, link: &Link) {
+			self.0.push(format!(
+				"start {}",
+				link.from.file_name().unwrap().to_string_lossy()
+			));
+		}
+
+		fn resource_created(&mut self, _link: &Link, _resource: &Resource, nth: usize) {
+			self.0.push(format!("created {}", nth));
+		}
+
+		fn binary_copied(&mut self, _link: &Link, _resource: &Resource, nth: usize) {
+			self.0.push(format!("copied {}", nth));
+		}
+
+		fn link_finished(&mut self, _link: &Link, timing: &Timing) {
+			self.0.push(format!("finished {} outputs", timing.outputs));
+		}
+
+		fn finished(&mut self, summary: &Summary) {
+			self.0.push(format!("finished {} bytes", summary.bytes));
+		}
+
+		fn plan(&mut self, _config: &Config, plan: &Plan) -> Result<()> {
+			self.0.push(format!("plan {} sources", plan.sources.len()));
+			Ok(())
+		}
+
+		fn error(&mut self, _error: &anyhow::Error) {
+			self.0.push("error".into());
+		}
+	}
+
+	#[test]
+	fn report_build_progress() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.build();
+		fs::create_dir_all(&config.source).unwrap();
+		fs::write(config.source.join("a.bin"), [0xff, 0xfe, 0x00]).unwrap();
+
+		let mut recorder = Recorder::default();
+		build_project(&config, &BuildOptions::default(), &mut recorder).unwrap();
+
+		let expect = vec![
+			"config",
+			"start a.bin",
+			"copied 0",
+			"finished 1 outputs",
+			"finished 3 bytes",
+		];
+		assert_eq!(recorder.0, expect);
+	}
+
+	#[test]
+	fn report_dry_run_plan() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.build();
+		fs::create_dir_all(&config.source).unwrap();
+		fs::write(config.source.join("a.txt"), "a").unwrap();
+
+		let options = BuildOptions {
+			dry_run: true,
+			..BuildOptions::default()
+		};
+		let mut recorder = Recorder::default();
+		build_project(&config, &options, &mut recorder).unwrap();
+
+		assert_eq!(recorder.0, vec!["config", "plan 1 sources"]);
+		assert!(!config.dest.exists());
+	}
+
+	#[test]
+	fn suspend_progress_bar() {
+		let mut reporter = TerminalReporter::new().with_progress(true);
+		reporter.planned(1, 1);
+		assert!(BAR.lock().unwrap().is_some());
+		assert_eq!(suspend(|| 42), 42);
+
+		reporter.error(&anyhow::anyhow!("failed"));
+		assert!(BAR.lock().unwrap().is_none());
+		assert_eq!(suspend(|| 42), 42);
+	}
+}
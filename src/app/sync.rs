@@ -1,56 +1,267 @@
-use super::{check_expression_block, message};
-use crate::config::Config;
-use crate::core::{Link, Walker};
+use super::event::Summary;
+use super::report::{Reporter, Timing};
+use super::{check_expression_block, interrupt, BuildOptions, Failures, MessageFormat, Rendered};
+use crate::config::{CleanMode, Config};
+use crate::core::manifest::{hash_bytes, output_key, Manifest, SourceEntry};
+use crate::core::merge::Merges;
+use crate::core::plan::Plan;
+use crate::core::staging::Staging;
+use crate::core::Walker;
+use crate::error::SourceError;
+use crate::share::{is_unchanged, read_file, write_file};
 use crate::utils::StringStream;
 use anyhow::Result;
 use megumax_template::{Resource, Template};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-pub fn build_project(config: &Config) -> Result<()> {
-	message::config_info(config);
+/// Walk the source directory and expand every file into the resources it produces.
+pub fn plan_project(config: &Config) -> Result<Plan> {
+	plan_sources(config, &mut Failures::new(false))
+}
+
+/// Like [`plan_project`], but the sources that can't be planned are returned with their errors instead of failing.
+pub fn plan_project_keep_going(config: &Config) -> Result<(Plan, Vec<SourceError>)> {
+	let mut failures = Failures::new(true);
+	let plan = plan_sources(config, &mut failures)?;
+	Ok((plan, failures.errors))
+}
 
-	let files = Walker::from_config(&config);
-	config.clear_build_dir()?;
+fn plan_sources(config: &Config, failures: &mut Failures) -> Result<Plan> {
+	let files = Walker::from_config(config);
+	let mut plan = Plan::new();
 
 	for link in files {
 		let link = link?;
+		let from = link.from.clone();
+		failures.record(&from, plan.push(link, &config.template))?;
+	}
+
+	Ok(plan)
+}
+
+/// Build the project into its output directory, reporting progress to `reporter`.
+pub fn build_project(
+	config: &Config,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Result<()> {
+	let started = Instant::now();
+
+	// A JSON dry run prints the plan as a single document instead.
+	if !(options.dry_run && options.message_format == MessageFormat::Json) {
+		reporter.config_info(config);
+	}
+
+	let mut failures = Failures::new(options.keep_going);
+	let plan = plan_sources(config, &mut failures)?;
+
+	if options.dry_run {
+		reporter.plan(config, &plan)?;
+		plan.check_collisions(config)?;
+		return failures.finish();
+	}
+
+	// Every output is known before touching the output directory, so a collision leaves it untouched.
+	plan.check_collisions(config)?;
+	config.check_dest()?;
+
+	let previous = Manifest::load(config);
+
+	// Without a manifest there is no way to tell which files are ours, so everything else gets removed.
+	if previous.is_none() && config.clean == CleanMode::All {
+		config.check_build_dir(options.force)?;
+	}
+
+	let mut staging = Staging::new(config)?;
+	let mut manifest = Manifest::new(config);
+	let mut merges = Merges::new(plan.merge_targets(config));
+	let mut summary = Summary {
+		sources: plan.sources.len(),
+		..Summary::default()
+	};
+	reporter.planned(plan.sources.len(), plan.resource_count());
+
+	for planned in plan.sources {
+		interrupt::check()?;
+		let link = planned.link;
+
+		// A merged output depends on every source contributing to it, so those are always rendered.
+		let merging = planned.resources.iter().any(|r| merges.contains(&r.path));
+
+		// Sources the caller knows are unchanged aren't even read.
+		if let Some(entry) = previous
+			.as_ref()
+			.filter(|_| !merging && !options.is_changed(config, &link))
+			.and_then(|m| m.cached(config, &link))
+		{
+			manifest.insert(config, &link, entry.clone());
+			summary.unchanged += 1;
+			continue;
+		}
+
+		let source_started = Instant::now();
+		let content = match failures.record(&link.from, read_file(&link.from))? {
+			Some(content) => content,
+			None => continue,
+		};
+		let hash = hash_bytes(&content);
 
-		message::create(&link);
-		let resources = link.to_resources(&config.template)?;
-		resources
-			.map(|x| create(x, &link, &config.keys))
-			.try_for_each(|result| result.map(|x| message::create_resource(&x)))?;
-		message::newline();
+		if let Some(entry) = previous
+			.as_ref()
+			.filter(|_| !merging)
+			.and_then(|m| m.unchanged(config, &link, &hash))
+		{
+			reporter.link_unchanged(&link);
+			manifest.insert(config, &link, entry.clone());
+			summary.unchanged += 1;
+			continue;
+		}
+
+		reporter.link_start(&link);
+		summary.rendered += 1;
+		let mut entry = SourceEntry::new(hash);
+		let mut timing = Timing::default();
+
+		for (nth, resource) in planned.resources.into_iter().enumerate() {
+			let Rendered {
+				content: output,
+				binary,
+			} = render(&resource, &content, &config.keys);
+			let key = output_key(config, &resource.path);
+
+			if merges.contains(&resource.path) {
+				// The hash is only known once every part is merged.
+				merges.add(&resource.path, &link.from, nth, output);
+				entry.outputs.insert(key, String::new());
+			} else {
+				let staged = stage(&mut staging, &resource.path, &output);
+
+				match failures.record(&link.from, staged)? {
+					Some(true) => timing.bytes += output.len() as u64,
+					Some(false) => (),
+					None => break,
+				}
+
+				entry.outputs.insert(key, hash_bytes(&output));
+			}
+
+			timing.outputs += 1;
+			summary.resources += 1;
+			summary.binaries += binary as usize;
+
+			if binary {
+				reporter.binary_copied(&link, &resource, nth);
+			} else {
+				reporter.resource_created(&link, &resource, nth);
+			}
+		}
+
+		manifest.insert(config, &link, entry);
+		timing.elapsed = source_started.elapsed();
+		summary.bytes += timing.bytes;
+		reporter.link_finished(&link, &timing);
 	}
 
+	// Merged outputs would miss the parts of failing sources.
+	failures.finish()?;
+
+	for (path, content) in merges.finish()? {
+		if stage(&mut staging, &path, &content)? {
+			summary.bytes += content.len() as u64;
+		}
+
+		let key = output_key(config, &path);
+		manifest.update_output(&key, &hash_bytes(&content));
+		summary.merged += 1;
+
+		reporter.merged(&path, config.merge_strategy(&key));
+	}
+
+	interrupt::check()?;
+
+	// Nothing in the output directory changed until now, so any failure above leaves the previous build intact. The
+	// staging directory then takes its place as a whole. When the files can only be moved one at a time, the previous
+	// manifest stays in place until it's done, so the next build renders again any output a failure left behind.
+	config.mark_build_dir()?;
+	staging.commit()?;
+	manifest.clean(previous.as_ref(), config)?;
+	manifest.stamp(config);
+	manifest.save(config)?;
+
+	summary.elapsed_ms = started.elapsed().as_millis() as u64;
+	reporter.finished(&summary);
+
 	Ok(())
 }
 
-fn create(resource: Resource, link: &Link, keys: &Template) -> Result<Resource> {
-	let link = link.with_resource(&resource);
+/// Render every output of `plan` in memory, keyed by its path, without touching the output directory.
+pub fn render_project(config: &Config, plan: Plan) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+	let mut merges = Merges::new(plan.merge_targets(config));
+	let mut outputs = BTreeMap::new();
+
+	for planned in plan.sources {
+		interrupt::check()?;
+		let content = read_file(&planned.link.from)?;
+
+		for (nth, resource) in planned.resources.into_iter().enumerate() {
+			let output = render(&resource, &content, &config.keys).content;
+
+			if merges.contains(&resource.path) {
+				merges.add(&resource.path, &planned.link.from, nth, output);
+			} else {
+				outputs.insert(resource.path, output);
+			}
+		}
+	}
+
+	outputs.extend(merges.finish()?);
+	Ok(outputs)
+}
+
+/// Render a single resource outside of a build.
+pub fn render_resource(resource: &Resource, content: &[u8], keys: &Template) -> Vec<u8> {
+	render(resource, content, keys).content
+}
+
+/// Write `content` into the staging directory, unless the output at `path` already has exactly that content.
+///
+/// Returns whether it was written.
+fn stage(staging: &mut Staging, path: &Path, content: &[u8]) -> Result<bool> {
+	if is_unchanged(path, content) {
+		return Ok(false);
+	}
 
-	let reader = BufReader::new(link.read()?);
-	let writer = BufWriter::new(link.create()?);
+	write_file(staging.stage(path), content)?;
+	Ok(true)
+}
 
+/// Render a single resource, binary files are copied as they are.
+fn render(resource: &Resource, content: &[u8], keys: &Template) -> Rendered {
 	let apply_template = |content: String| {
 		let content = resource.replace(&content);
 		keys.replace(&content)
 	};
 
-	let result = generate_text(reader, writer, apply_template, check_expression_block);
+	let mut output = Vec::new();
+	let result = generate_text(content, &mut output, apply_template, check_expression_block);
 
 	// Returning error mean the file is a binary file and we just have to copy its content and not replacing anything.
 	if result.is_err() {
-		std::fs::remove_file(&link.to)?;
-		std::fs::copy(&link.from, &link.to)?;
+		output = content.to_vec();
 	}
 
-	Ok(resource)
+	Rendered {
+		content: output,
+		binary: result.is_err(),
+	}
 }
 
 /// Read string from the Reader, transform it using `f` function and write it into the Writer.
 ///
-/// Note: This function never load the entire string into memory.
+/// Note: The reader is usually the whole source, which builds already read into memory to hash it.
 fn generate_text<R, W, F, G>(reader: R, mut writer: W, f: F, g: G) -> Result<()>
 where
 	R: Read,
@@ -71,7 +282,199 @@ where
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::app::report::SilentReporter;
+	use crate::config::{ConfigBuilder, MergeRule, MergeStrategy};
+	use crate::core::staging::staging_dir;
+	use crate::error::{ErrorKind, SourceErrors};
 	use proptest::prelude::*;
+	use std::fs;
+
+	#[test]
+	fn incremental_build() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		fs::create_dir_all(&src).unwrap();
+		fs::write(src.join("a.txt"), "a").unwrap();
+		fs::write(src.join("b.txt"), "b").unwrap();
+
+		let config = ConfigBuilder::new(src.clone(), build.clone(), root.join("megu.toml")).build();
+		let options = BuildOptions::default();
+		build_project(&config, &options, &mut SilentReporter).unwrap();
+
+		let manifest = Manifest::load(&config).unwrap();
+		assert_eq!(manifest.sources.len(), 2);
+
+		// An output edited by hand is rendered again even though its source didn't change.
+		fs::write(build.join("a.txt"), "edited").unwrap();
+		build_project(&config, &options, &mut SilentReporter).unwrap();
+		assert_eq!(fs::read_to_string(build.join("a.txt")).unwrap(), "a");
+
+		fs::remove_file(src.join("b.txt")).unwrap();
+		fs::write(build.join("untracked.txt"), "").unwrap();
+		build_project(&config, &options, &mut SilentReporter).unwrap();
+
+		assert!(build.join("a.txt").is_file());
+		assert!(!build.join("b.txt").exists());
+		assert!(!build.join("untracked.txt").exists());
+		assert_eq!(Manifest::load(&config).unwrap().sources.len(), 1);
+	}
+
+	#[test]
+	fn rebuild_changed_sources() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		fs::create_dir_all(src.join("dir")).unwrap();
+		fs::write(src.join("a.txt"), "a").unwrap();
+		fs::write(src.join("dir").join("b.txt"), "b").unwrap();
+
+		let config = ConfigBuilder::new(src.clone(), build.clone(), root.join("megu.toml")).build();
+		build_project(&config, &BuildOptions::default(), &mut SilentReporter).unwrap();
+
+		// Only the listed sources are read again, even though both changed.
+		fs::write(src.join("a.txt"), "new a").unwrap();
+		fs::write(src.join("dir").join("b.txt"), "new b").unwrap();
+		let changed = |paths: &[&str]| BuildOptions {
+			changed: Some(paths.iter().map(PathBuf::from).collect()),
+			..BuildOptions::default()
+		};
+		build_project(&config, &changed(&["a.txt"]), &mut SilentReporter).unwrap();
+
+		assert_eq!(fs::read_to_string(build.join("a.txt")).unwrap(), "new a");
+		assert_eq!(
+			fs::read_to_string(build.join("dir").join("b.txt")).unwrap(),
+			"b"
+		);
+
+		// A changed directory covers its files, and a deleted source loses its outputs.
+		fs::remove_file(src.join("a.txt")).unwrap();
+		build_project(&config, &changed(&["a.txt", "dir"]), &mut SilentReporter).unwrap();
+
+		assert!(!build.join("a.txt").exists());
+		assert_eq!(
+			fs::read_to_string(build.join("dir").join("b.txt")).unwrap(),
+			"new b"
+		);
+	}
+
+	#[test]
+	fn dry_run_writes_nothing() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		fs::create_dir_all(&src).unwrap();
+		fs::write(src.join("a.txt"), "a").unwrap();
+
+		let config = ConfigBuilder::new(src, build.clone(), root.join("megu.toml")).build();
+		let options = BuildOptions {
+			dry_run: true,
+			..BuildOptions::default()
+		};
+		build_project(&config, &options, &mut SilentReporter).unwrap();
+
+		assert!(!build.exists());
+		assert!(!staging_dir(&build).exists());
+	}
+
+	#[test]
+	fn merge_colliding_outputs() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		fs::create_dir_all(&src).unwrap();
+		fs::write(src.join("[lang].json"), r#"{"name": "[lang]"}"#).unwrap();
+		fs::write(src.join("en.json"), r#"{"name": "plain", "extra": 1}"#).unwrap();
+
+		let mut pool = megumax_template::Pool::default_rule();
+		pool.insert("[lang]".into(), vec!["en".into()]);
+		let merge = MergeRule {
+			files: globset::Glob::new("*.json").unwrap().compile_matcher(),
+			strategy: MergeStrategy::JsonObjectMerge,
+		};
+		let config = ConfigBuilder::new(src, build.clone(), root.join("megu.toml"))
+			.with_template(pool)
+			.with_merge(vec![merge])
+			.build();
+
+		let expect = "{\n  \"extra\": 1,\n  \"name\": \"plain\"\n}\n";
+
+		for _ in 0..2 {
+			build_project(&config, &BuildOptions::default(), &mut SilentReporter).unwrap();
+			let merged = fs::read_to_string(build.join("en.json")).unwrap();
+			assert_eq!(merged, expect);
+		}
+
+		let manifest = Manifest::load(&config).unwrap();
+		let hash = hash_bytes(expect.as_bytes());
+		assert!(manifest
+			.sources
+			.values()
+			.all(|entry| entry.outputs.values().all(|h| *h == hash)));
+	}
+
+	#[test]
+	fn failed_build_keeps_previous_output() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		fs::create_dir_all(&src).unwrap();
+		fs::write(src.join("a.txt"), "old").unwrap();
+
+		let mut pool = megumax_template::Pool::default_rule();
+		pool.insert("[n]".into(), vec!["x".into(), "x".into()]);
+		let merge = MergeRule {
+			files: globset::Glob::new("*.json").unwrap().compile_matcher(),
+			strategy: MergeStrategy::JsonObjectMerge,
+		};
+		let config = ConfigBuilder::new(src.clone(), build.clone(), root.join("megu.toml"))
+			.with_template(pool)
+			.with_merge(vec![merge])
+			.build();
+
+		build_project(&config, &BuildOptions::default(), &mut SilentReporter).unwrap();
+
+		// `a.txt` renders fine, but merging the invalid JSON fails afterward.
+		fs::write(src.join("a.txt"), "new").unwrap();
+		fs::write(src.join("[n].json"), "not json").unwrap();
+		assert!(build_project(&config, &BuildOptions::default(), &mut SilentReporter).is_err());
+
+		assert_eq!(fs::read_to_string(build.join("a.txt")).unwrap(), "old");
+		assert!(!build.join("x.json").exists());
+		assert!(!staging_dir(&build).exists());
+	}
+
+	#[test]
+	fn keep_going_collects_every_error() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		fs::create_dir_all(&src).unwrap();
+		fs::write(src.join("a.txt"), "a").unwrap();
+		fs::write(src.join("[missing].txt"), "").unwrap();
+		fs::write(src.join("[unknown].txt"), "").unwrap();
+
+		let config = ConfigBuilder::new(src.clone(), build.clone(), root.join("megu.toml")).build();
+		let options = BuildOptions {
+			keep_going: true,
+			..BuildOptions::default()
+		};
+		let err = build_project(&config, &options, &mut SilentReporter).unwrap_err();
+		let errors = err.downcast_ref::<SourceErrors>().unwrap();
+
+		let paths: Vec<_> = errors.0.iter().map(|e| e.path.clone()).collect();
+		assert_eq!(
+			paths,
+			vec![src.join("[missing].txt"), src.join("[unknown].txt")]
+		);
+		assert_eq!(ErrorKind::of(&err), ErrorKind::MissingKey);
+		assert!(!build.join("a.txt").exists());
+
+		// Without it the build stops at the first error.
+		let err =
+			build_project(&config, &BuildOptions::default(), &mut SilentReporter).unwrap_err();
+		assert!(err.downcast_ref::<SourceErrors>().is_none());
+	}
 
 	proptest! {
 		#[test]
@@ -0,0 +1,48 @@
+use super::diff::changes;
+use super::message;
+use crate::config::Config;
+use anyhow::Result;
+
+/// Check that the output directory is exactly what a fresh build would produce, without modifying it.
+///
+/// Returns whether the output directory is up to date.
+pub fn verify_project(config: &Config) -> Result<bool> {
+	message::config_info(config);
+	let changes = changes(config)?;
+
+	for change in &changes {
+		message::stale(change);
+	}
+
+	message::verify_summary(changes.len());
+	Ok(changes.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::app::report::SilentReporter;
+	use crate::app::{build_project, BuildOptions};
+	use crate::config::ConfigBuilder;
+	use std::fs;
+
+	#[test]
+	fn detect_stale_output() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.build();
+		fs::create_dir_all(&config.source).unwrap();
+		fs::write(config.source.join("a.txt"), "a").unwrap();
+
+		assert!(!verify_project(&config).unwrap());
+
+		build_project(&config, &BuildOptions::default(), &mut SilentReporter).unwrap();
+		assert!(verify_project(&config).unwrap());
+
+		fs::write(config.dest.join("extra.txt"), "").unwrap();
+		assert!(!verify_project(&config).unwrap());
+		assert!(config.dest.join("extra.txt").is_file());
+	}
+}
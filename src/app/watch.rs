@@ -0,0 +1,361 @@
+use super::report::Reporter;
+use super::{build_project, interrupt, BuildOptions};
+use crate::config::Config;
+use crate::core::staging::is_build_path;
+use crate::share::absolute;
+use crate::workspace::Workspace;
+use anyhow::{bail, Context, Result};
+use notify_debouncer_full::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+/// Changes within this delay of each other are handled together, editors often write a file several times on save.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How often Ctrl-C is checked while waiting for changes.
+const POLL: Duration = Duration::from_millis(100);
+
+type FileWatcher = Debouncer<RecommendedWatcher, RecommendedCache>;
+
+/// Directories of a loaded config, as absolute paths to compare them with the paths of events.
+struct Watched {
+	config: Config,
+	source: PathBuf,
+	dest: PathBuf,
+}
+
+impl Watched {
+	fn new(config: Config) -> Result<Self> {
+		let source = absolute(&config.source)?;
+		let dest = absolute(&config.dest)?;
+
+		Ok(Self {
+			config,
+			source,
+			dest,
+		})
+	}
+
+	/// Path of a changed source relative to the source directory, ignoring what the build itself writes.
+	fn source_path(&self, path: &Path) -> Option<PathBuf> {
+		if is_build_path(&self.dest, path) {
+			return None;
+		}
+
+		path.strip_prefix(&self.source).ok().map(Path::to_path_buf)
+	}
+}
+
+/// Build the project, then rebuild it whenever a source or the config file at `config_path` changes, until Ctrl-C.
+///
+/// Only the sources that changed are rendered again, and the outputs of deleted sources are removed. A config change
+/// reloads it with `load` and rebuilds everything, so does a change to the workspace root config of a member. Errors are given to `reporter` without stopping the watch.
+pub fn watch_project<F>(
+	config_path: &Path,
+	load: F,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Result<()>
+where
+	F: Fn() -> Result<Config>,
+{
+	watch_until(
+		config_path,
+		load,
+		options,
+		reporter,
+		interrupt::is_interrupted,
+	)
+}
+
+/// Same as [`watch_project`], stopping once `stop` returns `true` instead of on Ctrl-C.
+fn watch_until<F, S>(
+	config_path: &Path,
+	load: F,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+	stop: S,
+) -> Result<()>
+where
+	F: Fn() -> Result<Config>,
+	S: Fn() -> bool,
+{
+	let shown = config_path;
+	let config_path = absolute(config_path)?;
+	let config_dir = config_path
+		.parent()
+		.with_context(|| format!("`{}` has no parent directory", config_path.display()))?;
+
+	// Members inherit keys and templates from the workspace root, so its config is watched as well.
+	let mut configs = vec![config_path.clone()];
+	if let Some(workspace) = Workspace::find_parent(&config_path)? {
+		configs.push(workspace.config_path);
+	}
+
+	let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+	let mut watcher = new_debouncer(DEBOUNCE, None, tx).with_context(|| "Starting file watcher")?;
+
+	// Editors often replace the file on save, which a watch on the file itself wouldn't follow.
+	for path in &configs {
+		let dir = path.parent().unwrap_or(config_dir);
+		watcher
+			.watch(dir, RecursiveMode::NonRecursive)
+			.with_context(|| format!("Watching `{}`", dir.display()))?;
+	}
+
+	let mut watched = reload(&load, None, &mut watcher, options, reporter);
+
+	while !stop() {
+		let events = match rx.recv_timeout(POLL) {
+			Ok(Ok(events)) => events,
+			Ok(Err(errors)) => {
+				for e in errors {
+					reporter.error(&anyhow::Error::from(e).context("Watching files failed"));
+				}
+				continue;
+			}
+			Err(RecvTimeoutError::Timeout) => continue,
+			Err(RecvTimeoutError::Disconnected) => bail!("File watcher stopped unexpectedly"),
+		};
+
+		// Reading files during a build shows up as access events, which would otherwise rebuild forever.
+		let paths: BTreeSet<_> = events
+			.into_iter()
+			.filter(|event| !event.kind.is_access())
+			.flat_map(|event| event.event.paths)
+			.collect();
+
+		if let Some(path) = configs.iter().find(|path| paths.contains(*path)) {
+			reporter.changed(if *path == config_path { shown } else { path });
+			watched = reload(&load, watched, &mut watcher, options, reporter);
+			continue;
+		}
+
+		let watched = match &watched {
+			Some(watched) => watched,
+			None => continue,
+		};
+
+		let changed: BTreeSet<_> = paths
+			.iter()
+			.filter_map(|path| watched.source_path(path))
+			.collect();
+
+		if changed.is_empty() {
+			continue;
+		}
+
+		for path in &changed {
+			reporter.changed(&watched.config.source.join(path));
+		}
+
+		let options = BuildOptions {
+			changed: Some(changed),
+			..options.clone()
+		};
+		if let Err(e) = build_project(&watched.config, &options, reporter) {
+			reporter.error(&e);
+		}
+	}
+
+	Ok(())
+}
+
+/// Load the config again and rebuild everything, moving the watch to the new source directory.
+///
+/// A config that fails to load is reported and sources are ignored until it's fixed.
+fn reload<F>(
+	load: &F,
+	previous: Option<Watched>,
+	watcher: &mut FileWatcher,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Option<Watched>
+where
+	F: Fn() -> Result<Config>,
+{
+	if let Some(previous) = &previous {
+		if let Err(e) = watcher.unwatch(&previous.source) {
+			log::debug!("Failed to unwatch {}: {}", previous.source.display(), e);
+		}
+	}
+
+	let watched = load().and_then(Watched::new).and_then(|watched| {
+		watcher
+			.watch(&watched.source, RecursiveMode::Recursive)
+			.with_context(|| format!("Watching `{}`", watched.config.source.display()))?;
+		Ok(watched)
+	});
+
+	match watched {
+		Ok(watched) => {
+			if let Err(e) = build_project(&watched.config, options, reporter) {
+				reporter.error(&e);
+			}
+
+			reporter.watching(&watched.config);
+			Some(watched)
+		}
+		Err(e) => {
+			reporter.error(&e);
+			reporter.paused();
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::app::report::SilentReporter;
+	use crate::config::{load_config, CONFIG_FILE};
+	use crate::toml::overrides::Overrides;
+	use std::fs;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::time::Instant;
+
+	/// Wait for the watcher to catch up, failing the test after a while.
+	fn wait_for<F: Fn() -> bool>(what: &str, condition: F) {
+		let started = Instant::now();
+
+		while !condition() {
+			assert!(
+				started.elapsed() < Duration::from_secs(10),
+				"Timed out waiting for {}",
+				what
+			);
+			std::thread::sleep(Duration::from_millis(50));
+		}
+	}
+
+	fn read(path: &Path) -> String {
+		fs::read_to_string(path).unwrap_or_default()
+	}
+
+	#[test]
+	fn rebuild_on_change() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		let config_path = root.join("megu.toml");
+		fs::create_dir_all(&src).unwrap();
+		fs::write(&config_path, "[build]\n[keys]\nname = \"megu\"\n").unwrap();
+		fs::write(src.join("a.txt"), "[name]").unwrap();
+
+		let stop = AtomicBool::new(false);
+
+		std::thread::scope(|scope| {
+			let watch = scope.spawn(|| {
+				let load = || load_config(&config_path);
+				let options = BuildOptions::default();
+				let stop = || stop.load(Ordering::SeqCst);
+				watch_until(&config_path, load, &options, &mut SilentReporter, stop)
+			});
+
+			wait_for("the first build", || read(&build.join("a.txt")) == "megu");
+
+			fs::write(src.join("a.txt"), "hello [name]").unwrap();
+			wait_for("a changed source", || {
+				read(&build.join("a.txt")) == "hello megu"
+			});
+
+			fs::write(src.join("b.txt"), "b").unwrap();
+			wait_for("a new source", || build.join("b.txt").is_file());
+
+			fs::remove_file(src.join("b.txt")).unwrap();
+			wait_for("a deleted source", || !build.join("b.txt").exists());
+
+			fs::write(&config_path, "[build]\n[keys]\nname = \"max\"\n").unwrap();
+			wait_for("a config change", || {
+				read(&build.join("a.txt")) == "hello max"
+			});
+
+			stop.store(true, Ordering::SeqCst);
+			watch.join().unwrap().unwrap();
+		});
+	}
+
+	#[test]
+	fn rebuild_on_workspace_change() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let member = root.join("member");
+		let (src, build) = (member.join("src"), member.join("build"));
+		let config_path = member.join(CONFIG_FILE);
+		fs::create_dir_all(&src).unwrap();
+		fs::write(&config_path, "[build]\n").unwrap();
+		fs::write(src.join("a.txt"), "[name]").unwrap();
+
+		let workspace = "[workspace]\nmembers = [\"member\"]\n[keys]\n";
+		fs::write(
+			root.join(CONFIG_FILE),
+			format!("{}name = \"megu\"\n", workspace),
+		)
+		.unwrap();
+
+		let stop = AtomicBool::new(false);
+
+		std::thread::scope(|scope| {
+			let watch = scope.spawn(|| {
+				let load = || {
+					let workspace = Workspace::find_parent(&config_path)?.unwrap();
+					workspace.load_member(&config_path, &Overrides::new())
+				};
+				let options = BuildOptions::default();
+				let stop = || stop.load(Ordering::SeqCst);
+				watch_until(&config_path, load, &options, &mut SilentReporter, stop)
+			});
+
+			wait_for("the first build", || read(&build.join("a.txt")) == "megu");
+
+			fs::write(
+				root.join(CONFIG_FILE),
+				format!("{}name = \"max\"\n", workspace),
+			)
+			.unwrap();
+			wait_for("a workspace change", || read(&build.join("a.txt")) == "max");
+
+			stop.store(true, Ordering::SeqCst);
+			watch.join().unwrap().unwrap();
+		});
+	}
+
+	#[test]
+	fn pause_on_broken_config() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let (src, build) = (root.join("src"), root.join("build"));
+		let config_path = root.join("megu.toml");
+		fs::create_dir_all(&src).unwrap();
+		fs::write(&config_path, "[build\n").unwrap();
+		fs::write(src.join("a.txt"), "a").unwrap();
+
+		let stop = AtomicBool::new(false);
+
+		std::thread::scope(|scope| {
+			let watch = scope.spawn(|| {
+				let load = || load_config(&config_path);
+				let options = BuildOptions::default();
+				let stop = || stop.load(Ordering::SeqCst);
+				watch_until(&config_path, load, &options, &mut SilentReporter, stop)
+			});
+
+			// Sources are ignored until the config is fixed.
+			std::thread::sleep(DEBOUNCE * 2);
+			fs::write(src.join("a.txt"), "edited").unwrap();
+			std::thread::sleep(DEBOUNCE * 2);
+			assert!(!build.exists());
+
+			fs::write(&config_path, "[build]\n").unwrap();
+			wait_for("the fixed config", || {
+				read(&build.join("a.txt")) == "edited"
+			});
+
+			stop.store(true, Ordering::SeqCst);
+			watch.join().unwrap().unwrap();
+		});
+	}
+}
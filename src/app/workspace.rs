@@ -0,0 +1,56 @@
+use super::report::Reporter;
+use super::{build_project, interrupt, BuildOptions};
+use crate::error::{MemberError, WorkspaceError};
+use crate::toml::overrides::Overrides;
+use crate::workspace::{Member, Workspace};
+use anyhow::{Context, Result};
+
+/// Build every selected member of the workspace.
+///
+/// The build stops at the first failing member, unless `options.keep_going` is set, then every member is built
+/// and the failures are returned together as a [`WorkspaceError`].
+pub fn build_workspace(
+	workspace: &Workspace,
+	members: &[&Member],
+	overrides: &Overrides,
+	options: &BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Result<()> {
+	let mut failed = Vec::new();
+
+	for member in members {
+		interrupt::check()?;
+		reporter.member(&member.name);
+
+		let result = workspace
+			.load_member(&member.config_path, overrides)
+			.and_then(|config| build_project(&config, options, reporter));
+
+		match result {
+			Ok(()) => {}
+			Err(error) if options.keep_going => {
+				failed.push(MemberError {
+					name: member.name.clone(),
+					error,
+				});
+			}
+			Err(error) => {
+				return Err(error)
+					.with_context(|| format!("Building workspace member `{}`", member.name));
+			}
+		}
+	}
+
+	let names: Vec<_> = failed.iter().map(|member| member.name.as_str()).collect();
+	reporter.workspace_finished(members.len(), &names);
+
+	if !failed.is_empty() {
+		return Err(WorkspaceError {
+			failed,
+			total: members.len(),
+		}
+		.into());
+	}
+
+	Ok(())
+}
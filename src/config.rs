@@ -1,29 +1,164 @@
-use crate::share::replace_prefix;
+use crate::error::ConfigError;
+use crate::share::{absolute, replace_prefix};
+use crate::toml::overrides::Overrides;
 use crate::toml::ConfigFormat;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
 use megumax_template::{Pool, Template};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+pub const CONFIG_FILE: &str = "megu.toml";
+
+/// File written into every output directory megumax manages, required before the directory can be cleared.
+pub const MARKER_FILE: &str = ".megumax";
+
+const MARKER_CONTENT: &str =
+	"This directory is generated by megumax, builds remove the files in it that they no longer produce.\n";
+
 pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+	load_config_with(path, &Overrides::default())
+}
+
+/// Load the config file and merge `overrides` on top of it before compiling.
+pub fn load_config_with<P: AsRef<Path>>(path: P, overrides: &Overrides) -> Result<Config> {
+	let path = path.as_ref();
+	let mut format = read_format(path)?;
+	overrides.apply(&mut format)?;
+	format.compile(path.to_path_buf())
+}
+
+/// Read and validate the config file without compiling it.
+pub fn read_format<P: AsRef<Path>>(path: P) -> Result<ConfigFormat> {
 	let path = path.as_ref();
 	log::debug!("Load config from {}", path.display());
 	let content = read_from_path(path)?;
-	let format: ConfigFormat = toml::from_str(&content)?;
-	let config = format.compile(path.to_path_buf());
-	Ok(config)
+	let format = ConfigFormat::parse(&content, path)?;
+	Ok(format)
 }
 
 pub fn load_from_string(content: &str) -> Result<Config> {
-	let format: ConfigFormat = toml::from_str(content)?;
+	let format = ConfigFormat::parse(content, Path::new(CONFIG_FILE))?;
 	log::debug!("Config Content: {:#?}", format);
-	let config = format.compile("megu.toml".into());
-	Ok(config)
+	format.compile(CONFIG_FILE.into())
+}
+
+/// Search `start` and its parent directories for a config file, the same way cargo finds `Cargo.toml`.
+pub fn find_config<P: AsRef<Path>>(start: P) -> Option<PathBuf> {
+	let start = start.as_ref();
+	let path = start
+		.ancestors()
+		.map(|dir| dir.join(CONFIG_FILE))
+		.find(|path| path.is_file())?;
+
+	// Keep the path short when the config is in the starting directory.
+	if path.parent() == Some(start) {
+		Some(CONFIG_FILE.into())
+	} else {
+		Some(path)
+	}
+}
+
+/// Locate the config file in the current directory or one of its parents.
+pub fn discover_config() -> Result<PathBuf> {
+	let current_dir = std::env::current_dir().with_context(|| "Reading current directory")?;
+	find_config(&current_dir).ok_or_else(|| {
+		ConfigError::new(format!(
+			"Cannot find `{}` in `{}` or any parent directory",
+			CONFIG_FILE,
+			current_dir.display()
+		))
+		.into()
+	})
 }
 
 pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<String> {
 	let path = path.as_ref();
-	std::fs::read_to_string(path)
-		.with_context(|| format!("Cannot find config file at `{}`", path.display()))
+	std::fs::read_to_string(path).map_err(|e| {
+		ConfigError::new(format!(
+			"Cannot find config file at `{}`: {}",
+			path.display(),
+			e
+		))
+		.into()
+	})
+}
+
+/// How files that a build no longer produces are removed from the output directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CleanMode {
+	/// The output directory only ever contains what the last build produced.
+	#[default]
+	All,
+	/// Only remove outputs that a previous build produced and this one doesn't, leaving every other file alone.
+	Prune,
+}
+
+/// How the outputs of several sources or variants writing to the same file are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeStrategy {
+	/// Colliding outputs fail the build.
+	#[default]
+	Error,
+	/// Merge JSON objects recursively, later values replace earlier ones.
+	JsonObjectMerge,
+	/// Like `JsonObjectMerge`, but arrays are concatenated instead of replaced.
+	JsonArrayConcat,
+	/// Concatenate the outputs, each starting on a new line.
+	AppendLines,
+}
+
+impl MergeStrategy {
+	/// Name of the strategy as written in the config.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Error => "error",
+			Self::JsonObjectMerge => "json-object-merge",
+			Self::JsonArrayConcat => "json-array-concat",
+			Self::AppendLines => "append-lines",
+		}
+	}
+}
+
+/// Outputs matching `files`, relative to the output directory, are merged with `strategy`.
+#[derive(Debug, Clone)]
+pub struct MergeRule {
+	pub files: GlobMatcher,
+	pub strategy: MergeStrategy,
+}
+
+/// Glob patterns matched as a single set, keeping the patterns themselves around.
+#[derive(Debug, Clone, Default)]
+pub struct GlobList {
+	globs: Vec<Glob>,
+	set: GlobSet,
+}
+
+impl GlobList {
+	pub fn new(globs: Vec<Glob>) -> Result<Self> {
+		let mut builder = GlobSetBuilder::new();
+
+		for glob in &globs {
+			builder.add(glob.clone());
+		}
+
+		let set = builder.build()?;
+		Ok(Self { globs, set })
+	}
+
+	pub fn is_match(&self, path: &Path) -> bool {
+		self.set.is_match(path)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.globs.is_empty()
+	}
+
+	pub fn globs(&self) -> &[Glob] {
+		&self.globs
+	}
 }
 
 #[derive(Debug, Default)]
@@ -33,6 +168,9 @@ pub struct ConfigBuilder {
 	config_path: PathBuf,
 	template: Pool,
 	keys: Template,
+	clean: CleanMode,
+	preserve: GlobList,
+	merge: Vec<MergeRule>,
 }
 
 impl ConfigBuilder {
@@ -55,6 +193,21 @@ impl ConfigBuilder {
 		self
 	}
 
+	pub fn with_clean(mut self, clean: CleanMode) -> Self {
+		self.clean = clean;
+		self
+	}
+
+	pub fn with_preserve(mut self, preserve: GlobList) -> Self {
+		self.preserve = preserve;
+		self
+	}
+
+	pub fn with_merge(mut self, merge: Vec<MergeRule>) -> Self {
+		self.merge = merge;
+		self
+	}
+
 	pub fn build(self) -> Config {
 		Config {
 			source: self.source,
@@ -62,6 +215,9 @@ impl ConfigBuilder {
 			config_path: self.config_path,
 			template: self.template,
 			keys: self.keys,
+			clean: self.clean,
+			preserve: self.preserve,
+			merge: self.merge,
 		}
 	}
 }
@@ -73,6 +229,11 @@ pub struct Config {
 	pub config_path: PathBuf,
 	pub template: Pool,
 	pub keys: Template,
+	pub clean: CleanMode,
+	/// Files in the output directory that are never deleted, relative to the output directory.
+	pub preserve: GlobList,
+	/// Checked in order, the first matching rule decides how colliding outputs are combined.
+	pub merge: Vec<MergeRule>,
 }
 
 impl Config {
@@ -82,19 +243,88 @@ impl Config {
 }
 
 impl Config {
-	pub fn clear_build_dir(&self) -> Result<()> {
-		log::debug!("Clearing destination directory...");
+	/// Refuse output directories that would wipe something other than generated files when cleared.
+	///
+	/// Checked when the config is compiled, and again right before files are deleted.
+	pub fn check_dest(&self) -> Result<()> {
+		let dest = absolute(&self.dest)?;
+
+		if dest.parent().is_none() {
+			bail!(ConfigError::new(format!(
+				"Refusing to use the filesystem root `{}` as the output directory",
+				dest.display()
+			)));
+		}
+
+		let config_dir = self.config_path.parent().unwrap_or_else(|| Path::new(""));
+		let mut protected = vec![
+			("source directory", absolute(&self.source)?),
+			("config directory", absolute(config_dir)?),
+		];
+
+		if let Some(home) = dirs::home_dir() {
+			protected.push(("home directory", absolute(&home)?));
+		}
+
+		for (name, path) in protected {
+			if path.starts_with(&dest) {
+				bail!(ConfigError::new(format!(
+					"Refusing to use `{}` as the output directory because clearing it would delete the {} `{}`",
+					self.dest.display(),
+					name,
+					path.display()
+				)));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Whether the file at `relative`, a path inside the output directory, must be kept.
+	pub fn is_preserved(&self, relative: &Path) -> bool {
+		self.preserve.is_match(relative)
+	}
+
+	pub fn merge_strategy(&self, relative: &Path) -> MergeStrategy {
+		self.merge
+			.iter()
+			.find(|rule| rule.files.is_match(relative))
+			.map(|rule| rule.strategy)
+			.unwrap_or_default()
+	}
 
+	pub fn marker_path(&self) -> PathBuf {
+		self.dest.join(MARKER_FILE)
+	}
+
+	/// Refuse to take over a non-empty output directory without the marker file, unless `force` is set.
+	pub fn check_build_dir(&self, force: bool) -> Result<()> {
 		let path = &self.dest;
 
-		if !path.exists() {
-			log::debug!("Build directory doesn't exists, skipped.");
+		if force || !path.exists() || self.marker_path().is_file() || is_empty_dir(path)? {
 			return Ok(());
 		}
 
-		std::fs::remove_dir_all(path)?;
-		Ok(())
+		bail!(ConfigError::new(format!(
+			"`{}` wasn't created by megumax (missing `{}` file), pass `--force` to delete it anyway",
+			path.display(),
+			MARKER_FILE
+		)))
 	}
+
+	/// Write the marker file that allows the output directory to be cleared by later builds.
+	pub fn mark_build_dir(&self) -> Result<()> {
+		let path = self.marker_path();
+		std::fs::create_dir_all(&self.dest)
+			.and_then(|_| std::fs::write(&path, MARKER_CONTENT))
+			.with_context(|| format!("Writing marker file `{}`", path.display()))
+	}
+}
+
+fn is_empty_dir(path: &Path) -> Result<bool> {
+	let mut entries = std::fs::read_dir(path)
+		.with_context(|| format!("Reading build directory `{}`", path.display()))?;
+	Ok(entries.next().is_none())
 }
 
 pub fn resolve_symbol(path: PathBuf) -> PathBuf {
@@ -102,3 +332,100 @@ pub fn resolve_symbol(path: PathBuf) -> PathBuf {
 	let result = shellexpand::tilde(&path_str);
 	PathBuf::from(result.as_ref())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::ErrorKind;
+	use crate::toml::ConfigFormat;
+
+	fn try_compile(content: &str, path: &str) -> Result<Config> {
+		let format = ConfigFormat::parse(content, Path::new(path)).unwrap();
+		format.compile(path.into())
+	}
+
+	fn compile(content: &str, path: &str) -> Config {
+		try_compile(content, path).unwrap()
+	}
+
+	#[test]
+	fn relative_to_config() {
+		let config = compile("[build]\nsrc = \"foo\"\noutput = \"bar\"", "sub/megu.toml");
+		assert_eq!(config.source, PathBuf::from("sub/foo"));
+		assert_eq!(config.dest, PathBuf::from("sub/bar"));
+	}
+
+	#[test]
+	fn config_in_current_dir() {
+		let config = compile("[build]", CONFIG_FILE);
+		assert_eq!(config.source, PathBuf::from("src"));
+		assert_eq!(config.dest, PathBuf::from("build"));
+	}
+
+	#[test]
+	fn absolute_path() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().join("out");
+		let path = dir.path().join("sub").join(CONFIG_FILE);
+		let content = format!("[build]\noutput = {:?}", root.display().to_string());
+		let config = compile(&content, path.to_str().unwrap());
+		assert_eq!(config.dest, root);
+	}
+
+	#[test]
+	fn reject_dangerous_output() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join(CONFIG_FILE);
+		let path = path.to_str().unwrap();
+		let check = |output: &str| {
+			let content = format!("[build]\nsrc = \"src\"\noutput = {:?}", output);
+			try_compile(&content, path)
+		};
+
+		for output in &[".", "./", "src", "src/..", "..", "~", "/"] {
+			let err = check(output).unwrap_err();
+			assert_eq!(ErrorKind::of(&err), ErrorKind::Config, "{}", output);
+		}
+
+		assert!(check("build").is_ok());
+		assert!(check("src/build").is_ok());
+
+		// Configs built by hand are checked before deleting anything.
+		let config = ConfigBuilder::new(
+			dir.path().join("src"),
+			dir.path().to_path_buf(),
+			PathBuf::from(path),
+		)
+		.build();
+		assert!(config.check_dest().is_err());
+	}
+
+	#[test]
+	fn take_over_requires_marker() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		std::fs::create_dir_all(root.join("build")).unwrap();
+		std::fs::write(root.join("build").join("pack.mcmeta"), "{}").unwrap();
+
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join(CONFIG_FILE))
+				.build();
+		assert!(config.check_build_dir(false).is_err());
+		assert!(config.check_build_dir(true).is_ok());
+
+		config.mark_build_dir().unwrap();
+		assert!(config.check_build_dir(false).is_ok());
+	}
+
+	#[test]
+	fn find_in_parent() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let nested = root.join("src").join("nested");
+		std::fs::create_dir_all(&nested).unwrap();
+		std::fs::write(root.join(CONFIG_FILE), "[build]").unwrap();
+
+		assert_eq!(find_config(&nested), Some(root.join(CONFIG_FILE)));
+		assert_eq!(find_config(root), Some(PathBuf::from(CONFIG_FILE)));
+	}
+}
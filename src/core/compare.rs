@@ -0,0 +1,129 @@
+use super::manifest::{is_reserved, output_key, Manifest};
+use crate::config::{CleanMode, Config};
+use crate::share::list_files;
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Difference between a fresh render and the output directory, paths are relative to the output directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+	/// Rendered, but missing from the output directory.
+	Added { path: PathBuf, new: Vec<u8> },
+	/// In the output directory, but no longer rendered.
+	Removed { path: PathBuf, old: Vec<u8> },
+	Modified {
+		path: PathBuf,
+		old: Vec<u8>,
+		new: Vec<u8>,
+	},
+}
+
+impl Change {
+	pub fn path(&self) -> &Path {
+		match self {
+			Self::Added { path, .. } | Self::Removed { path, .. } | Self::Modified { path, .. } => {
+				path
+			}
+		}
+	}
+}
+
+/// Compare `outputs`, keyed by their path in the output directory, to the files that are actually there.
+///
+/// Files a build would leave alone, like preserved ones, aren't reported.
+pub fn compare(config: &Config, outputs: BTreeMap<PathBuf, Vec<u8>>) -> Result<Vec<Change>> {
+	let mut result = Vec::new();
+	let mut rendered = BTreeSet::new();
+
+	for (path, new) in outputs {
+		let path = output_key(config, &path);
+
+		match fs::read(config.dest.join(&path)) {
+			Ok(old) if old == new => {}
+			Ok(old) => result.push(Change::Modified {
+				path: path.clone(),
+				old,
+				new,
+			}),
+			Err(_) => result.push(Change::Added {
+				path: path.clone(),
+				new,
+			}),
+		}
+
+		rendered.insert(path);
+	}
+
+	if config.dest.is_dir() {
+		// In prune mode only files a previous build produced would be removed.
+		let previous = Manifest::load(config);
+		let managed = |path: &Path| match (config.clean, &previous) {
+			(CleanMode::All, _) => true,
+			(CleanMode::Prune, Some(previous)) => previous.outputs().contains(path),
+			(CleanMode::Prune, None) => false,
+		};
+
+		for path in list_files(&config.dest)? {
+			if rendered.contains(&path)
+				|| is_reserved(&path)
+				|| config.is_preserved(&path)
+				|| !managed(&path)
+			{
+				continue;
+			}
+
+			let old = fs::read(config.dest.join(&path)).unwrap_or_default();
+			result.push(Change::Removed { path, old });
+		}
+	}
+
+	result.sort_by(|a, b| a.path().cmp(b.path()));
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+
+	#[test]
+	fn detect_changes() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.build();
+
+		let build = &config.dest;
+		fs::create_dir_all(build).unwrap();
+		fs::write(build.join("same.txt"), "same").unwrap();
+		fs::write(build.join("changed.txt"), "old").unwrap();
+		fs::write(build.join("stale.txt"), "stale").unwrap();
+		fs::write(build.join(crate::config::MARKER_FILE), "").unwrap();
+
+		let mut outputs = BTreeMap::new();
+		outputs.insert(build.join("same.txt"), b"same".to_vec());
+		outputs.insert(build.join("changed.txt"), b"new".to_vec());
+		outputs.insert(build.join("new.txt"), b"new".to_vec());
+
+		let changes = compare(&config, outputs).unwrap();
+		let expect = vec![
+			Change::Modified {
+				path: "changed.txt".into(),
+				old: b"old".to_vec(),
+				new: b"new".to_vec(),
+			},
+			Change::Added {
+				path: "new.txt".into(),
+				new: b"new".to_vec(),
+			},
+			Change::Removed {
+				path: "stale.txt".into(),
+				old: b"stale".to_vec(),
+			},
+		];
+		assert_eq!(changes, expect);
+	}
+}
@@ -1,3 +1,4 @@
+use super::staging::is_build_path;
 use ignore::gitignore::Gitignore;
 use std::path::{Path, PathBuf};
 
@@ -37,6 +38,7 @@ impl Filter {
 	}
 
 	fn is_build_dir<P: AsRef<Path>>(&self, path: P) -> bool {
-		path.as_ref().starts_with(&self.dest)
+		let path = path.as_ref();
+		is_build_path(&self.dest, path)
 	}
 }
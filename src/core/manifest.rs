@@ -0,0 +1,474 @@
+use super::Link;
+use crate::config::{CleanMode, Config, MARKER_FILE};
+use crate::share::remove_all_except;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// File inside the output directory recording what the previous build produced.
+pub const MANIFEST_FILE: &str = ".megumax-manifest.json";
+
+/// Bumped whenever the layout of the manifest changes, older manifests are then ignored.
+const MANIFEST_VERSION: u32 = 1;
+
+/// Outputs produced by a single source file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceEntry {
+	/// Hash of the source file content.
+	pub hash: String,
+	/// Hash of every output, keyed by its path relative to the output directory.
+	pub outputs: BTreeMap<PathBuf, String>,
+}
+
+impl SourceEntry {
+	pub fn new(hash: String) -> Self {
+		Self {
+			hash,
+			outputs: BTreeMap::new(),
+		}
+	}
+}
+
+/// Size and modification time of an output, when both are the same the output is assumed to be unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+	pub size: u64,
+	/// Nanoseconds since the Unix epoch.
+	pub modified: u64,
+}
+
+impl Stamp {
+	pub fn of(path: &Path) -> Option<Self> {
+		let metadata = fs::metadata(path).ok()?;
+		let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+		Some(Self {
+			size: metadata.len(),
+			modified: modified.as_nanos() as u64,
+		})
+	}
+}
+
+/// Record of a build, used to skip sources whose inputs haven't changed since the previous one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+	pub version: u32,
+	/// Hash of every config value the build used, see [`config_hash`].
+	pub config_hash: String,
+	/// Every source keyed by its path relative to the source directory.
+	pub sources: BTreeMap<PathBuf, SourceEntry>,
+	/// Stamp of every output once the build finished, keyed like [`SourceEntry::outputs`]. Outputs that kept theirs
+	/// aren't read again to check their hash.
+	#[serde(default)]
+	pub stamps: BTreeMap<PathBuf, Stamp>,
+}
+
+impl Manifest {
+	pub fn new(config: &Config) -> Self {
+		Self {
+			version: MANIFEST_VERSION,
+			config_hash: config_hash(config),
+			sources: BTreeMap::new(),
+			stamps: BTreeMap::new(),
+		}
+	}
+
+	pub fn path(config: &Config) -> PathBuf {
+		config.dest.join(MANIFEST_FILE)
+	}
+
+	/// Load the manifest of the previous build, if there is a usable one.
+	pub fn load(config: &Config) -> Option<Self> {
+		let path = Self::path(config);
+		let content = fs::read(&path).ok()?;
+
+		match serde_json::from_slice::<Self>(&content) {
+			Ok(manifest) if manifest.version == MANIFEST_VERSION => Some(manifest),
+			Ok(_) => {
+				log::debug!(
+					"Ignored manifest from an older release at {}",
+					path.display()
+				);
+				None
+			}
+			Err(e) => {
+				log::warn!("Ignored unreadable manifest at {}: {}", path.display(), e);
+				None
+			}
+		}
+	}
+
+	pub fn save(&self, config: &Config) -> Result<()> {
+		let path = Self::path(config);
+		let content = serde_json::to_vec_pretty(self)?;
+		fs::write(&path, content).with_context(|| format!("Writing manifest `{}`", path.display()))
+	}
+
+	/// Return the entry of `link` from this manifest if neither the source nor the config changed and every output
+	/// still has the content it was built with.
+	pub fn unchanged(&self, config: &Config, link: &Link, hash: &str) -> Option<&SourceEntry> {
+		self.cached(config, link).filter(|entry| entry.hash == hash)
+	}
+
+	/// Return the entry of `link` from this manifest if the config didn't change and every output still has the
+	/// content it was built with, without checking the source itself.
+	pub fn cached(&self, config: &Config, link: &Link) -> Option<&SourceEntry> {
+		if self.config_hash != config_hash(config) {
+			return None;
+		}
+
+		let entry = self.sources.get(&source_key(config, link)?)?;
+		let intact = entry.outputs.iter().all(|(path, hash)| {
+			let full = config.dest.join(path);
+
+			let stamp = self.stamps.get(path).copied();
+			if stamp.is_some() && stamp == Stamp::of(&full) {
+				return true;
+			}
+
+			fs::read(full)
+				.map(|content| hash_bytes(&content) == *hash)
+				.unwrap_or(false)
+		});
+
+		if intact {
+			Some(entry)
+		} else {
+			None
+		}
+	}
+
+	pub fn insert(&mut self, config: &Config, link: &Link, entry: SourceEntry) {
+		if let Some(key) = source_key(config, link) {
+			self.sources.insert(key, entry);
+		}
+	}
+
+	/// Record the hash of an output that several sources contribute to.
+	pub fn update_output(&mut self, relative: &Path, hash: &str) {
+		for entry in self.sources.values_mut() {
+			if let Some(previous) = entry.outputs.get_mut(relative) {
+				*previous = hash.to_owned();
+			}
+		}
+	}
+
+	/// Record the stamp of every output, once they are all in the output directory.
+	pub fn stamp(&mut self, config: &Config) {
+		let stamps = self
+			.outputs()
+			.into_iter()
+			.filter_map(|path| Some((path.to_path_buf(), Stamp::of(&config.dest.join(path))?)))
+			.collect();
+		self.stamps = stamps;
+	}
+
+	/// Every output path of this manifest, relative to the output directory.
+	pub fn outputs(&self) -> BTreeSet<&Path> {
+		self.sources
+			.values()
+			.flat_map(|entry| entry.outputs.keys())
+			.map(PathBuf::as_path)
+			.collect()
+	}
+
+	/// Delete every file in the output directory that this build didn't produce, along with the directories left empty.
+	pub fn remove_untracked(&self, config: &Config) -> Result<()> {
+		let outputs = self.outputs();
+		remove_all_except(&config.dest, |relative| {
+			outputs.contains(relative) || is_reserved(relative) || config.is_preserved(relative)
+		})?;
+		Ok(())
+	}
+
+	/// Delete the outputs `previous` produced that this build no longer does, leaving every other file alone.
+	pub fn prune(&self, previous: &Manifest, config: &Config) -> Result<()> {
+		let outputs = self.outputs();
+
+		for relative in previous.outputs().difference(&outputs) {
+			if config.is_preserved(relative) {
+				continue;
+			}
+
+			let path = config.dest.join(relative);
+			if !path.is_file() {
+				continue;
+			}
+
+			log::debug!("Remove stale output {}", path.display());
+			fs::remove_file(&path).with_context(|| format!("Removing `{}`", path.display()))?;
+			remove_empty_parents(&path, &config.dest);
+		}
+
+		Ok(())
+	}
+
+	/// Remove files the previous build left behind, as configured by `clean`.
+	pub fn clean(&self, previous: Option<&Manifest>, config: &Config) -> Result<()> {
+		match (config.clean, previous) {
+			(CleanMode::All, _) => self.remove_untracked(config),
+			(CleanMode::Prune, Some(previous)) => self.prune(previous, config),
+			(CleanMode::Prune, None) => Ok(()),
+		}
+	}
+}
+
+/// Remove the parent directories of `path` that became empty, stopping at `root`.
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+	let parents = path.ancestors().skip(1).take_while(|dir| *dir != root);
+
+	for dir in parents {
+		// Fails once a directory isn't empty.
+		if fs::remove_dir(dir).is_err() {
+			break;
+		}
+	}
+}
+
+/// Files megumax keeps in the output directory for itself.
+pub fn is_reserved(relative: &Path) -> bool {
+	relative == Path::new(MARKER_FILE) || relative == Path::new(MANIFEST_FILE)
+}
+
+fn source_key(config: &Config, link: &Link) -> Option<PathBuf> {
+	link.from
+		.strip_prefix(&config.source)
+		.ok()
+		.map(Path::to_path_buf)
+}
+
+/// Path of an output relative to the output directory, as stored in the manifest.
+pub fn output_key(config: &Config, path: &Path) -> PathBuf {
+	path.strip_prefix(&config.dest)
+		.unwrap_or(path)
+		.to_path_buf()
+}
+
+pub fn hash_bytes(content: &[u8]) -> String {
+	blake3::hash(content).to_hex().to_string()
+}
+
+/// Hash every config value that changes what a build writes or removes, in a stable order.
+///
+/// Paths aren't part of it, the manifest lives in the output directory and is keyed by paths inside the source one.
+pub fn config_hash(config: &Config) -> String {
+	let keys: BTreeMap<_, _> = config.keys.iter().collect();
+	let pools: BTreeMap<_, _> = config.template.iter().collect();
+
+	let mut hasher = blake3::Hasher::new();
+
+	for (key, value) in keys {
+		hasher
+			.update(key.as_bytes())
+			.update(b"\0")
+			.update(value.as_bytes())
+			.update(b"\0");
+	}
+
+	hasher.update(b"\0");
+
+	for (key, values) in pools {
+		hasher.update(key.as_bytes()).update(b"\0");
+		for value in values {
+			hasher.update(value.as_bytes()).update(b"\0");
+		}
+	}
+
+	hasher.update(b"\0");
+	hasher.update(format!("{:?}", config.clean).as_bytes());
+
+	for glob in config.preserve.globs() {
+		hasher.update(b"\0").update(glob.glob().as_bytes());
+	}
+
+	hasher.update(b"\0");
+
+	for rule in &config.merge {
+		hasher
+			.update(rule.files.glob().glob().as_bytes())
+			.update(b"\0")
+			.update(rule.strategy.name().as_bytes())
+			.update(b"\0");
+	}
+
+	hasher.finalize().to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::{ConfigBuilder, GlobList};
+	use megumax_template::Template;
+	use std::collections::HashMap;
+
+	fn config(root: &Path, value: &str) -> Config {
+		let mut keys = HashMap::new();
+		keys.insert("[foo]".to_owned(), value.to_owned());
+
+		ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+			.with_keys(Template::new(keys))
+			.build()
+	}
+
+	fn manifest_with_output(config: &Config, link: &Link) -> Manifest {
+		let mut entry = SourceEntry::new(hash_bytes(b"source"));
+		entry.outputs.insert("a.txt".into(), hash_bytes(b"output"));
+
+		let mut manifest = Manifest::new(config);
+		manifest.insert(config, link, entry);
+		manifest
+	}
+
+	#[test]
+	fn detect_changes() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root, "bar");
+		let link = Link::new(
+			root.join("src").join("a.txt"),
+			root.join("build").join("a.txt"),
+		);
+		let manifest = manifest_with_output(&config, &link);
+
+		fs::create_dir_all(root.join("build")).unwrap();
+		fs::write(root.join("build").join("a.txt"), "output").unwrap();
+
+		let hash = hash_bytes(b"source");
+		assert!(manifest.unchanged(&config, &link, &hash).is_some());
+		assert!(manifest
+			.unchanged(&config, &link, &hash_bytes(b"edited"))
+			.is_none());
+
+		let other = self::config(root, "baz");
+		assert!(manifest.unchanged(&other, &link, &hash).is_none());
+
+		let preserve = GlobList::new(vec![globset::Glob::new("*.mcmeta").unwrap()]).unwrap();
+		let mut other = self::config(root, "bar");
+		other.preserve = preserve;
+		assert!(manifest.unchanged(&other, &link, &hash).is_none());
+
+		fs::write(root.join("build").join("a.txt"), "edited by hand").unwrap();
+		assert!(manifest.unchanged(&config, &link, &hash).is_none());
+
+		fs::remove_file(root.join("build").join("a.txt")).unwrap();
+		assert!(manifest.unchanged(&config, &link, &hash).is_none());
+	}
+
+	#[test]
+	fn trust_unchanged_stamps() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root, "bar");
+		let link = Link::new(
+			root.join("src").join("a.txt"),
+			root.join("build").join("a.txt"),
+		);
+		let mut manifest = manifest_with_output(&config, &link);
+		let output = root.join("build").join("a.txt");
+		let hash = hash_bytes(b"source");
+
+		fs::create_dir_all(root.join("build")).unwrap();
+		fs::write(&output, "output").unwrap();
+		manifest.stamp(&config);
+		let modified = fs::metadata(&output).unwrap().modified().unwrap();
+
+		// Same size and modification time, so the content isn't read.
+		fs::write(&output, "edited").unwrap();
+		let file = fs::File::options().write(true).open(&output).unwrap();
+		file.set_modified(modified).unwrap();
+		assert!(manifest.unchanged(&config, &link, &hash).is_some());
+
+		fs::write(&output, "edited by hand").unwrap();
+		assert!(manifest.unchanged(&config, &link, &hash).is_none());
+	}
+
+	#[test]
+	fn save_and_load() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root, "bar");
+		let link = Link::new(
+			root.join("src").join("a.txt"),
+			root.join("build").join("a.txt"),
+		);
+		let manifest = manifest_with_output(&config, &link);
+
+		fs::create_dir_all(&config.dest).unwrap();
+		manifest.save(&config).unwrap();
+		assert_eq!(Manifest::load(&config), Some(manifest));
+
+		fs::write(Manifest::path(&config), "not json").unwrap();
+		assert_eq!(Manifest::load(&config), None);
+	}
+
+	#[test]
+	fn prune_stale_outputs() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root, "bar");
+		let link = Link::new(
+			root.join("src").join("a.txt"),
+			root.join("build").join("a.txt"),
+		);
+		let previous = manifest_with_output(&config, &link);
+		let current = Manifest::new(&config);
+
+		let build = &config.dest;
+		fs::create_dir_all(build).unwrap();
+		fs::write(build.join("a.txt"), "output").unwrap();
+		fs::write(build.join("pack.mcmeta"), "{}").unwrap();
+
+		current.prune(&previous, &config).unwrap();
+
+		assert!(!build.join("a.txt").exists());
+		assert!(build.join("pack.mcmeta").is_file());
+	}
+
+	#[test]
+	fn keep_preserved_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let preserve = GlobList::new(vec![globset::Glob::new(".git/**").unwrap()]).unwrap();
+		let config =
+			ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml"))
+				.with_preserve(preserve)
+				.build();
+
+		let build = &config.dest;
+		fs::create_dir_all(build.join(".git").join("refs")).unwrap();
+		fs::write(build.join(".git").join("refs").join("main"), "").unwrap();
+		fs::write(build.join("stale.txt"), "").unwrap();
+
+		Manifest::new(&config).remove_untracked(&config).unwrap();
+
+		assert!(build.join(".git").join("refs").join("main").is_file());
+		assert!(!build.join("stale.txt").exists());
+	}
+
+	#[test]
+	fn remove_untracked_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root, "bar");
+		let link = Link::new(
+			root.join("src").join("a.txt"),
+			root.join("build").join("a.txt"),
+		);
+		let manifest = manifest_with_output(&config, &link);
+
+		let build = &config.dest;
+		fs::create_dir_all(build.join("old").join("nested")).unwrap();
+		fs::write(build.join("a.txt"), "output").unwrap();
+		fs::write(build.join("old").join("nested").join("b.txt"), "stale").unwrap();
+		fs::write(build.join(MARKER_FILE), "").unwrap();
+
+		manifest.remove_untracked(&config).unwrap();
+
+		assert!(build.join("a.txt").is_file());
+		assert!(build.join(MARKER_FILE).is_file());
+		assert!(!build.join("old").exists());
+	}
+}
@@ -0,0 +1,214 @@
+use crate::config::MergeStrategy;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Rendered outputs waiting to be combined, keyed by the output they're written to.
+#[derive(Debug, Default)]
+pub struct Merges {
+	outputs: BTreeMap<PathBuf, Merge>,
+}
+
+#[derive(Debug)]
+struct Merge {
+	strategy: MergeStrategy,
+	/// Ordered by source path, then by the position of the variant.
+	parts: BTreeMap<(PathBuf, usize), Vec<u8>>,
+}
+
+impl Merges {
+	pub fn new(targets: BTreeMap<PathBuf, MergeStrategy>) -> Self {
+		let outputs = targets
+			.into_iter()
+			.map(|(path, strategy)| {
+				let parts = BTreeMap::new();
+				(path, Merge { strategy, parts })
+			})
+			.collect();
+
+		Self { outputs }
+	}
+
+	pub fn contains(&self, path: &Path) -> bool {
+		self.outputs.contains_key(path)
+	}
+
+	/// Add the `nth` variant of `source` rendered to `path`.
+	pub fn add(&mut self, path: &Path, source: &Path, nth: usize, content: Vec<u8>) {
+		if let Some(merge) = self.outputs.get_mut(path) {
+			merge.parts.insert((source.to_path_buf(), nth), content);
+		}
+	}
+
+	/// Combine the parts of every output.
+	pub fn finish(self) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+		self.outputs
+			.into_iter()
+			.map(|(path, merge)| {
+				let content = merge
+					.combine()
+					.with_context(|| format!("Merging outputs into `{}`", path.display()))?;
+				Ok((path, content))
+			})
+			.collect()
+	}
+}
+
+impl Merge {
+	fn combine(self) -> Result<Vec<u8>> {
+		let parts = self
+			.parts
+			.into_iter()
+			.map(|((source, _), content)| (source, content));
+
+		match self.strategy {
+			MergeStrategy::Error => bail!("Outputs with the `error` strategy can't be merged"),
+			MergeStrategy::JsonObjectMerge => merge_json(parts, false),
+			MergeStrategy::JsonArrayConcat => merge_json(parts, true),
+			MergeStrategy::AppendLines => Ok(append_lines(parts)),
+		}
+	}
+}
+
+fn merge_json<I>(parts: I, concat: bool) -> Result<Vec<u8>>
+where
+	I: IntoIterator<Item = (PathBuf, Vec<u8>)>,
+{
+	let mut result: Option<Value> = None;
+
+	for (source, content) in parts {
+		let value: Value = serde_json::from_slice(&content)
+			.with_context(|| format!("Output of `{}` is not valid JSON", source.display()))?;
+
+		let expected = matches!(
+			(&value, concat),
+			(Value::Object(_), _) | (Value::Array(_), true)
+		);
+
+		if !expected {
+			let kind = if concat { "object or array" } else { "object" };
+			bail!(
+				"Output of `{}` must be a JSON {} to be merged",
+				source.display(),
+				kind
+			);
+		}
+
+		result = Some(match result {
+			None => value,
+			Some(base) if std::mem::discriminant(&base) != std::mem::discriminant(&value) => {
+				bail!(
+					"Output of `{}` doesn't have the same JSON type as the outputs before it",
+					source.display()
+				)
+			}
+			Some(base) => merge_value(base, value, concat),
+		});
+	}
+
+	let mut output = serde_json::to_vec_pretty(&result.unwrap_or(Value::Null))?;
+	output.push(b'\n');
+	Ok(output)
+}
+
+fn merge_value(base: Value, value: Value, concat: bool) -> Value {
+	match (base, value) {
+		(Value::Object(mut base), Value::Object(value)) => {
+			for (key, value) in value {
+				let merged = match base.remove(&key) {
+					Some(previous) => merge_value(previous, value, concat),
+					None => value,
+				};
+				base.insert(key, merged);
+			}
+			Value::Object(base)
+		}
+		(Value::Array(mut base), Value::Array(value)) if concat => {
+			base.extend(value);
+			Value::Array(base)
+		}
+		(_, value) => value,
+	}
+}
+
+fn append_lines<I>(parts: I) -> Vec<u8>
+where
+	I: IntoIterator<Item = (PathBuf, Vec<u8>)>,
+{
+	let mut output = Vec::new();
+
+	for (_, content) in parts {
+		if !output.is_empty() && !output.ends_with(b"\n") {
+			output.push(b'\n');
+		}
+		output.extend(content);
+	}
+
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn merge(strategy: MergeStrategy, parts: &[(&str, usize, &str)]) -> Result<String> {
+		let path = Path::new("out.json");
+		let mut targets = BTreeMap::new();
+		targets.insert(path.to_path_buf(), strategy);
+
+		let mut merges = Merges::new(targets);
+		for (source, nth, content) in parts {
+			merges.add(path, Path::new(source), *nth, content.as_bytes().to_vec());
+		}
+
+		let (_, content) = merges.finish()?.remove(0);
+		Ok(String::from_utf8(content).unwrap())
+	}
+
+	#[test]
+	fn json_object_merge() {
+		let parts = [
+			("b.json", 0, r#"{"lang": {"b": "B"}, "list": [2]}"#),
+			("a.json", 0, r#"{"lang": {"a": "A"}, "list": [1]}"#),
+		];
+		let result = merge(MergeStrategy::JsonObjectMerge, &parts).unwrap();
+		let value: Value = serde_json::from_str(&result).unwrap();
+
+		assert_eq!(
+			value,
+			serde_json::json!({"lang": {"a": "A", "b": "B"}, "list": [2]})
+		);
+	}
+
+	#[test]
+	fn json_array_concat() {
+		let parts = [
+			("tag.json", 1, r#"{"values": ["b"]}"#),
+			("tag.json", 0, r#"{"values": ["a"]}"#),
+			("other.json", 0, r#"{"replace": false, "values": ["c"]}"#),
+		];
+		let result = merge(MergeStrategy::JsonArrayConcat, &parts).unwrap();
+		let value: Value = serde_json::from_str(&result).unwrap();
+
+		assert_eq!(
+			value,
+			serde_json::json!({"replace": false, "values": ["c", "a", "b"]})
+		);
+	}
+
+	#[test]
+	fn append_lines_in_order() {
+		let parts = [("b", 0, "b\n"), ("a", 1, "a2"), ("a", 0, "a1")];
+		let result = merge(MergeStrategy::AppendLines, &parts).unwrap();
+		assert_eq!(result, "a1\na2\nb\n");
+	}
+
+	#[test]
+	fn reject_mismatched_json() {
+		let parts = [("a", 0, "{}"), ("b", 0, "[]")];
+		assert!(merge(MergeStrategy::JsonArrayConcat, &parts).is_err());
+		assert!(merge(MergeStrategy::JsonObjectMerge, &parts).is_err());
+		assert!(merge(MergeStrategy::JsonObjectMerge, &[("a", 0, "not json")]).is_err());
+	}
+}
@@ -1,5 +1,10 @@
+pub mod compare;
 mod filter;
 mod link;
+pub mod manifest;
+pub mod merge;
+pub mod plan;
+pub mod staging;
 mod walker;
 
 pub use filter::*;
@@ -8,9 +13,25 @@ pub use walker::*;
 
 pub mod special {
 	use megumax_template::Resource;
+	use std::collections::BTreeMap;
 
 	pub const NTH_TEMPLATE: &str = "[nth]";
 
+	/// Template values of the variant `resource` was expanded from, keyed by the pool name without brackets.
+	pub fn variant(resource: &Resource) -> BTreeMap<&str, &str> {
+		resource
+			.template
+			.iter()
+			.filter(|(key, _)| key.as_str() != NTH_TEMPLATE)
+			.map(|(key, value)| {
+				(
+					key.trim_start_matches('[').trim_end_matches(']'),
+					value.as_str(),
+				)
+			})
+			.collect()
+	}
+
 	pub fn nth_template((n, mut res): (usize, Resource)) -> Resource {
 		res.template.set(NTH_TEMPLATE.to_owned(), n.to_string());
 		res
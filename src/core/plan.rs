@@ -0,0 +1,283 @@
+use super::manifest::output_key;
+use super::special::NTH_TEMPLATE;
+use super::Link;
+use crate::config::{Config, MergeStrategy};
+use megumax_template::{Pool, Resource};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// A source file along with every resource it expands into.
+#[derive(Debug)]
+pub struct Planned {
+	pub link: Link,
+	pub resources: Vec<Resource>,
+}
+
+/// Every output of a build, computed before anything is written.
+#[derive(Debug, Default)]
+pub struct Plan {
+	pub sources: Vec<Planned>,
+}
+
+impl Plan {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Expand `link` with `pool` and add its resources to the plan.
+	pub fn push(&mut self, link: Link, pool: &Pool) -> anyhow::Result<()> {
+		let resources = link.to_resources(pool)?.collect();
+		self.sources.push(Planned { link, resources });
+		Ok(())
+	}
+
+	pub fn resource_count(&self) -> usize {
+		self.sources.iter().map(|s| s.resources.len()).sum()
+	}
+
+	/// Find every output path produced more than once, including paths that only differ in case.
+	pub fn collisions(&self) -> Vec<Collision> {
+		let mut exact: BTreeMap<&Path, Vec<Producer>> = BTreeMap::new();
+
+		for planned in &self.sources {
+			for resource in &planned.resources {
+				let producer = Producer::new(&planned.link.from, resource);
+				exact.entry(&resource.path).or_default().push(producer);
+			}
+		}
+
+		let mut result = Vec::new();
+		let mut folded: BTreeMap<String, Vec<&Path>> = BTreeMap::new();
+
+		for (path, producers) in &exact {
+			folded
+				.entry(path.to_string_lossy().to_lowercase())
+				.or_default()
+				.push(path);
+
+			if producers.len() > 1 {
+				result.push(Collision {
+					path: path.to_path_buf(),
+					producers: producers.clone(),
+					case_only: false,
+				});
+			}
+		}
+
+		for paths in folded.values().filter(|paths| paths.len() > 1) {
+			let producers = paths.iter().flat_map(|path| exact[path].clone()).collect();
+			result.push(Collision {
+				path: paths[0].to_path_buf(),
+				producers,
+				case_only: true,
+			});
+		}
+
+		result
+	}
+
+	/// Outputs produced more than once that `config` combines instead of rejecting.
+	pub fn merge_targets(&self, config: &Config) -> BTreeMap<PathBuf, MergeStrategy> {
+		self.collisions()
+			.into_iter()
+			.filter(|collision| !collision.case_only)
+			.map(|collision| {
+				let strategy = config.merge_strategy(&output_key(config, &collision.path));
+				(collision.path, strategy)
+			})
+			.filter(|(_, strategy)| *strategy != MergeStrategy::Error)
+			.collect()
+	}
+
+	/// Fail with every collision if any output would be written more than once without a merge strategy.
+	pub fn check_collisions(&self, config: &Config) -> Result<(), CollisionError> {
+		let merged = self.merge_targets(config);
+		let collisions: Vec<_> = self
+			.collisions()
+			.into_iter()
+			.filter(|collision| collision.case_only || !merged.contains_key(&collision.path))
+			.collect();
+
+		if collisions.is_empty() {
+			Ok(())
+		} else {
+			Err(CollisionError(collisions))
+		}
+	}
+}
+
+/// A source and the variant values that produce a given output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Producer {
+	pub source: PathBuf,
+	pub output: PathBuf,
+	/// Template values of the variant, sorted by key and without `[nth]`.
+	pub variant: Vec<(String, String)>,
+}
+
+impl Producer {
+	pub fn new(source: &Path, resource: &Resource) -> Self {
+		let mut variant: Vec<_> = resource
+			.template
+			.iter()
+			.filter(|(key, _)| key.as_str() != NTH_TEMPLATE)
+			.map(|(key, value)| (key.clone(), value.clone()))
+			.collect();
+		variant.sort();
+
+		Self {
+			source: source.to_path_buf(),
+			output: resource.path.clone(),
+			variant,
+		}
+	}
+}
+
+impl fmt::Display for Producer {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "`{}`", self.source.display())?;
+
+		if !self.variant.is_empty() {
+			let values: Vec<_> = self
+				.variant
+				.iter()
+				.map(|(key, value)| format!("{} = {:?}", key, value))
+				.collect();
+			write!(f, " with {}", values.join(", "))?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Several producers writing to the same output.
+#[derive(Debug, Clone)]
+pub struct Collision {
+	pub path: PathBuf,
+	pub producers: Vec<Producer>,
+	/// The paths are only the same on case-insensitive filesystems.
+	pub case_only: bool,
+}
+
+impl fmt::Display for Collision {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.case_only {
+			write!(
+				f,
+				"Outputs only differing in case collide on case-insensitive filesystems:"
+			)?;
+		} else {
+			write!(f, "`{}` is produced more than once:", self.path.display())?;
+		}
+
+		for producer in &self.producers {
+			write!(f, "\n  - ")?;
+			if self.case_only {
+				write!(f, "`{}` from ", producer.output.display())?;
+			}
+			write!(f, "{}", producer)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Error)]
+pub struct CollisionError(pub Vec<Collision>);
+
+impl fmt::Display for CollisionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let count = self.0.len();
+		let noun = if count == 1 {
+			"collision"
+		} else {
+			"collisions"
+		};
+		write!(f, "Found {} output {}, nothing was written", count, noun)?;
+
+		for collision in &self.0 {
+			write!(f, "\n\n{}", collision)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn plan(pool: &Pool, sources: &[&str]) -> Plan {
+		let mut plan = Plan::new();
+
+		for source in sources {
+			let link = Link::new(
+				Path::new("src").join(source),
+				Path::new("build").join(source),
+			);
+			plan.push(link, pool).unwrap();
+		}
+
+		plan
+	}
+
+	fn pool() -> Pool {
+		let mut pool = Pool::default_rule();
+		pool.insert("[color]".into(), vec!["red".into(), "blue".into()]);
+		pool
+	}
+
+	#[test]
+	fn no_collision() {
+		let plan = plan(&pool(), &["[color].md", "green.md"]);
+		assert!(plan.collisions().is_empty());
+		assert_eq!(plan.resource_count(), 3);
+	}
+
+	#[test]
+	fn source_collision() {
+		let plan = plan(&pool(), &["[color].md", "red.md"]);
+		let collisions = plan.collisions();
+
+		assert_eq!(collisions.len(), 1);
+		assert_eq!(collisions[0].path, PathBuf::from("build/red.md"));
+		assert!(!collisions[0].case_only);
+
+		let producers: Vec<_> = collisions[0]
+			.producers
+			.iter()
+			.map(|p| p.to_string())
+			.collect();
+		let mut expect = vec![
+			"`src/[color].md` with [color] = \"red\"".to_owned(),
+			"`src/red.md`".to_owned(),
+		];
+		expect.sort();
+		let mut producers = producers;
+		producers.sort();
+		assert_eq!(producers, expect);
+	}
+
+	#[test]
+	fn variant_collision() {
+		let mut pool = Pool::default_rule();
+		pool.insert("[color]".into(), vec!["red".into(), "red".into()]);
+		let plan = plan(&pool, &["[color].md"]);
+
+		let collisions = plan.collisions();
+		assert_eq!(collisions.len(), 1);
+		assert_eq!(collisions[0].producers.len(), 2);
+	}
+
+	#[test]
+	fn case_collision() {
+		let plan = plan(&pool(), &["[color].md", "Red.md"]);
+		let collisions = plan.collisions();
+
+		assert_eq!(collisions.len(), 1);
+		assert!(collisions[0].case_only);
+		assert_eq!(collisions[0].producers.len(), 2);
+	}
+}
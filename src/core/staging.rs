@@ -0,0 +1,272 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory next to `dest` that a build is rendered into before anything in `dest` changes.
+pub fn staging_dir(dest: &Path) -> PathBuf {
+	sibling(dest, "megumax-staging")
+}
+
+/// Directory next to `dest` that the previous output is moved to while the staging directory takes its place.
+pub fn previous_dir(dest: &Path) -> PathBuf {
+	sibling(dest, "megumax-previous")
+}
+
+/// Whether `path` is in the output directory `dest`, or in one of the directories a build uses next to it.
+pub fn is_build_path(dest: &Path, path: &Path) -> bool {
+	path.starts_with(dest)
+		|| path.starts_with(staging_dir(dest))
+		|| path.starts_with(previous_dir(dest))
+}
+
+fn sibling(dest: &Path, suffix: &str) -> PathBuf {
+	let name = dest
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	dest.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+/// Outputs of a build that haven't been moved into the output directory yet.
+///
+/// The staging directory is deleted when this is dropped, so a failed or interrupted build leaves nothing behind.
+#[derive(Debug)]
+pub struct Staging {
+	dir: PathBuf,
+	dest: PathBuf,
+	/// Staged files, relative to both the staging and the output directory.
+	files: Vec<PathBuf>,
+}
+
+impl Staging {
+	/// Create an empty staging directory, cleaning up after a build that was killed.
+	///
+	/// A build killed in the middle of [`Staging::commit`] can leave the previous output moved aside, it's put back.
+	pub fn new(config: &Config) -> Result<Self> {
+		let dir = staging_dir(&config.dest);
+		let previous = previous_dir(&config.dest);
+
+		if previous.exists() {
+			if config.dest.exists() {
+				log::debug!("Remove leftover previous output {}", previous.display());
+				fs::remove_dir_all(&previous).with_context(|| {
+					format!("Removing previous output `{}`", previous.display())
+				})?;
+			} else {
+				log::debug!("Restore previous output {}", previous.display());
+				fs::rename(&previous, &config.dest).with_context(|| {
+					format!("Restoring previous output `{}`", previous.display())
+				})?;
+			}
+		}
+
+		if dir.exists() {
+			log::debug!("Remove leftover staging directory {}", dir.display());
+			fs::remove_dir_all(&dir)
+				.with_context(|| format!("Removing staging directory `{}`", dir.display()))?;
+		}
+
+		fs::create_dir_all(&dir)
+			.with_context(|| format!("Creating staging directory `{}`", dir.display()))?;
+
+		Ok(Self {
+			dir,
+			dest: config.dest.clone(),
+			files: Vec::new(),
+		})
+	}
+
+	/// Return where the output at `path` has to be written instead.
+	pub fn stage(&mut self, path: &Path) -> PathBuf {
+		let relative = path.strip_prefix(&self.dest).unwrap_or(path).to_path_buf();
+		let staged = self.dir.join(&relative);
+		self.files.push(relative);
+		staged
+	}
+
+	/// Replace the output directory with the staging directory.
+	///
+	/// Files of the output directory that weren't staged are hard linked into the staging directory first, then the
+	/// output directory is moved aside and the staging directory takes its place. When that fails, like when the output
+	/// directory is a mount point, the staged files are moved one at a time instead, and a failure partway leaves some
+	/// of them in the output directory.
+	pub fn commit(self) -> Result<()> {
+		if self.dest.is_dir() {
+			match self.swap() {
+				Ok(()) => return Ok(()),
+				Err(e) => log::debug!("Moving staged files one at a time: {:#}", e),
+			}
+		}
+
+		self.move_files()
+	}
+
+	fn swap(&self) -> Result<()> {
+		let staged: HashSet<_> = self.files.iter().map(PathBuf::as_path).collect();
+		link_unstaged(&self.dest, &self.dir, Path::new(""), &staged)?;
+
+		let previous = previous_dir(&self.dest);
+		fs::rename(&self.dest, &previous)
+			.with_context(|| format!("Moving `{}` aside", self.dest.display()))?;
+
+		if let Err(e) = fs::rename(&self.dir, &self.dest) {
+			fs::rename(&previous, &self.dest)
+				.with_context(|| format!("Restoring previous output `{}`", previous.display()))?;
+			return Err(e).with_context(|| format!("Moving output to `{}`", self.dest.display()));
+		}
+
+		if let Err(e) = fs::remove_dir_all(&previous) {
+			log::debug!("Failed to remove {}: {}", previous.display(), e);
+		}
+
+		Ok(())
+	}
+
+	fn move_files(&self) -> Result<()> {
+		for relative in &self.files {
+			let (from, to) = (self.dir.join(relative), self.dest.join(relative));
+
+			if let Some(parent) = to.parent() {
+				fs::create_dir_all(parent)
+					.with_context(|| format!("Creating directory `{}`", parent.display()))?;
+			}
+
+			fs::rename(&from, &to)
+				.with_context(|| format!("Moving output to `{}`", to.display()))?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Drop for Staging {
+	fn drop(&mut self) {
+		if !self.dir.exists() {
+			return;
+		}
+
+		if let Err(e) = fs::remove_dir_all(&self.dir) {
+			log::debug!("Failed to remove {}: {}", self.dir.display(), e);
+		}
+	}
+}
+
+/// Hard link every file of `dest` that isn't in `staged` into `staging`, keeping their content and modification time.
+fn link_unstaged(
+	dest: &Path,
+	staging: &Path,
+	relative: &Path,
+	staged: &HashSet<&Path>,
+) -> Result<()> {
+	let dir = dest.join(relative);
+	fs::create_dir_all(staging.join(relative))
+		.with_context(|| format!("Creating directory `{}`", staging.join(relative).display()))?;
+
+	for entry in fs::read_dir(&dir).with_context(|| format!("Reading `{}`", dir.display()))? {
+		let entry = entry?;
+		let relative = relative.join(entry.file_name());
+
+		if entry.file_type()?.is_dir() {
+			link_unstaged(dest, staging, &relative, staged)?;
+		} else if !staged.contains(relative.as_path()) {
+			let (from, to) = (dest.join(&relative), staging.join(&relative));
+			fs::hard_link(&from, &to)
+				.with_context(|| format!("Linking `{}` to `{}`", from.display(), to.display()))?;
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ConfigBuilder;
+
+	fn config(root: &Path) -> Config {
+		ConfigBuilder::new(root.join("src"), root.join("build"), root.join("megu.toml")).build()
+	}
+
+	#[test]
+	fn commit_staged_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root);
+
+		let mut staging = Staging::new(&config).unwrap();
+		let staged = staging.stage(&config.dest.join("a").join("b.txt"));
+		fs::create_dir_all(staged.parent().unwrap()).unwrap();
+		fs::write(&staged, "b").unwrap();
+		assert!(!config.dest.exists());
+
+		staging.commit().unwrap();
+
+		assert_eq!(
+			fs::read_to_string(config.dest.join("a").join("b.txt")).unwrap(),
+			"b"
+		);
+		assert!(!staging_dir(&config.dest).exists());
+	}
+
+	#[test]
+	fn keep_unstaged_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root);
+		fs::create_dir_all(config.dest.join("empty")).unwrap();
+		fs::write(config.dest.join("kept.txt"), "kept").unwrap();
+		fs::write(config.dest.join("b.txt"), "old").unwrap();
+		let modified = fs::metadata(config.dest.join("kept.txt"))
+			.unwrap()
+			.modified()
+			.unwrap();
+
+		let mut staging = Staging::new(&config).unwrap();
+		fs::write(staging.stage(&config.dest.join("b.txt")), "new").unwrap();
+		staging.commit().unwrap();
+
+		let kept = config.dest.join("kept.txt");
+		assert_eq!(fs::read_to_string(&kept).unwrap(), "kept");
+		assert_eq!(fs::metadata(&kept).unwrap().modified().unwrap(), modified);
+		assert_eq!(
+			fs::read_to_string(config.dest.join("b.txt")).unwrap(),
+			"new"
+		);
+		assert!(config.dest.join("empty").is_dir());
+		assert!(!previous_dir(&config.dest).exists());
+		assert!(!staging_dir(&config.dest).exists());
+	}
+
+	#[test]
+	fn restore_previous_output() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root);
+
+		// Killed after moving the output aside, before the staging directory took its place.
+		let previous = previous_dir(&config.dest);
+		fs::create_dir_all(&previous).unwrap();
+		fs::write(previous.join("a.txt"), "a").unwrap();
+
+		drop(Staging::new(&config).unwrap());
+
+		assert_eq!(fs::read_to_string(config.dest.join("a.txt")).unwrap(), "a");
+		assert!(!previous.exists());
+	}
+
+	#[test]
+	fn discard_on_drop() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let config = config(root);
+
+		let mut staging = Staging::new(&config).unwrap();
+		fs::write(staging.stage(&config.dest.join("a.txt")), "a").unwrap();
+		drop(staging);
+
+		assert!(!staging_dir(&config.dest).exists());
+		assert!(!config.dest.exists());
+	}
+}
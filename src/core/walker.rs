@@ -40,7 +40,7 @@ use extras::*;
 mod extras {
 	use super::*;
 	use anyhow::Error;
-	pub use ignore::{DirEntry, Walk as Iter, WalkBuilder};
+	pub use ignore::{Walk as Iter, WalkBuilder};
 
 	pub fn create_walker(source: &Path, _dest: &Path) -> Iter {
 		WalkBuilder::new(source).hidden(false).build()
@@ -76,7 +76,7 @@ mod extras {
 	use futures::prelude::*;
 
 	pub fn create_walker(source: &Path, _dest: &Path) -> Iter {
-		Iter::new(&source)
+		Iter::new(source)
 	}
 
 	impl Walker {
@@ -0,0 +1,270 @@
+//! Error categories and the exit codes they map to.
+//!
+//! Errors travel through the crate as [`anyhow::Error`] so their context chain is printed unchanged,
+//! [`ErrorKind::of`] looks through that chain for the typed error at its root to pick the exit code.
+
+use crate::core::plan::CollisionError;
+use crate::toml::diagnostic::Diagnostics;
+use megumax_template::error::KeyLookUpError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// A config file that cannot be found, read or used as it is.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct ConfigError(String);
+
+impl ConfigError {
+	pub fn new(message: impl Into<String>) -> Self {
+		Self(message.into())
+	}
+}
+
+/// The build was stopped with Ctrl-C.
+#[derive(Debug, Error)]
+#[error("Build interrupted, the output directory was left untouched")]
+pub struct Interrupted;
+
+/// A source that failed to build.
+#[derive(Debug)]
+pub struct SourceError {
+	pub path: PathBuf,
+	pub error: anyhow::Error,
+}
+
+/// Every source that failed in a `--keep-going` build.
+#[derive(Debug, Error)]
+pub struct SourceErrors(pub Vec<SourceError>);
+
+impl SourceErrors {
+	/// The most severe category among the errors, see [`ErrorKind::max`].
+	pub fn kind(&self) -> ErrorKind {
+		self.0
+			.iter()
+			.map(|source| ErrorKind::of(&source.error))
+			.fold(ErrorKind::Other, ErrorKind::max)
+	}
+}
+
+impl fmt::Display for SourceErrors {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let count = self.0.len();
+		let noun = if count == 1 { "source" } else { "sources" };
+		write!(f, "{} {} failed to build, nothing was written", count, noun)?;
+
+		for source in &self.0 {
+			write!(f, "\n\n`{}`: {:#}", source.path.display(), source.error)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// A workspace member that failed to build.
+#[derive(Debug)]
+pub struct MemberError {
+	pub name: String,
+	pub error: anyhow::Error,
+}
+
+/// Every member that failed in a `--keep-going` workspace build.
+#[derive(Debug, Error)]
+pub struct WorkspaceError {
+	pub failed: Vec<MemberError>,
+	/// Number of members built, including the failed ones.
+	pub total: usize,
+}
+
+impl WorkspaceError {
+	/// The most severe category among the failed members, see [`ErrorKind::max`].
+	pub fn kind(&self) -> ErrorKind {
+		self.failed
+			.iter()
+			.map(|member| ErrorKind::of(&member.error))
+			.fold(ErrorKind::Other, ErrorKind::max)
+	}
+}
+
+impl fmt::Display for WorkspaceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let names: Vec<_> = self
+			.failed
+			.iter()
+			.map(|member| member.name.as_str())
+			.collect();
+		write!(
+			f,
+			"{} of {} workspace members failed to build: {}",
+			names.len(),
+			self.total,
+			names.join(", ")
+		)?;
+
+		for member in &self.failed {
+			write!(f, "\n\n`{}`: {:#}", member.name, member.error)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// What went wrong, as far as the exit code is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+	/// The config file is missing, malformed or invalid.
+	Config,
+	/// A template refers to a key that isn't defined.
+	MissingKey,
+	/// Reading or writing a file failed.
+	Io,
+	/// Several sources render to the same output path.
+	Collision,
+	/// Ctrl-C was pressed.
+	Interrupted,
+	/// Anything else.
+	Other,
+}
+
+impl ErrorKind {
+	/// Classify `err` by the outermost typed error in its chain, context messages are skipped.
+	pub fn of(err: &anyhow::Error) -> Self {
+		err.chain()
+			.find_map(|cause| {
+				if let Some(errors) = cause.downcast_ref::<SourceErrors>() {
+					Some(errors.kind())
+				} else if let Some(workspace) = cause.downcast_ref::<WorkspaceError>() {
+					Some(workspace.kind())
+				} else if cause.is::<ConfigError>()
+					|| cause.is::<Diagnostics>()
+					|| cause.is::<globset::Error>()
+					|| cause.is::<glob::PatternError>()
+				{
+					Some(ErrorKind::Config)
+				} else if cause.is::<KeyLookUpError>() {
+					Some(ErrorKind::MissingKey)
+				} else if cause.is::<CollisionError>() {
+					Some(ErrorKind::Collision)
+				} else if cause.is::<Interrupted>() {
+					Some(ErrorKind::Interrupted)
+				} else if cause.is::<io::Error>() {
+					Some(ErrorKind::Io)
+				} else {
+					None
+				}
+			})
+			.unwrap_or(ErrorKind::Other)
+	}
+
+	/// The more severe of `self` and `other`: an interruption first, then errors that stop a project
+	/// from loading or building at all, and [`ErrorKind::Other`] last.
+	pub fn max(self, other: Self) -> Self {
+		if other.severity() > self.severity() {
+			other
+		} else {
+			self
+		}
+	}
+
+	fn severity(self) -> u8 {
+		match self {
+			ErrorKind::Other => 0,
+			ErrorKind::Io => 1,
+			ErrorKind::MissingKey => 2,
+			ErrorKind::Collision => 3,
+			ErrorKind::Config => 4,
+			ErrorKind::Interrupted => 5,
+		}
+	}
+
+	/// The process exit code, distinct from the `1` of `megu diff` and the `10` of `megu verify`.
+	pub fn exit_code(self) -> i32 {
+		match self {
+			ErrorKind::Other => 2,
+			ErrorKind::Config => 3,
+			ErrorKind::MissingKey => 4,
+			ErrorKind::Io => 5,
+			ErrorKind::Collision => 6,
+			ErrorKind::Interrupted => 130,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use anyhow::{anyhow, Context};
+
+	#[test]
+	fn classify_by_root_cause() {
+		let missing: anyhow::Error = KeyLookUpError::new("color").into();
+		let missing = missing.context("Looking up keyword in `a.json`");
+		assert_eq!(ErrorKind::of(&missing), ErrorKind::MissingKey);
+
+		let io = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+			.context("Reading source")
+			.unwrap_err();
+		assert_eq!(ErrorKind::of(&io), ErrorKind::Io);
+
+		let config = anyhow!(ConfigError::new("Cannot find config file")).context("Loading");
+		assert_eq!(ErrorKind::of(&config), ErrorKind::Config);
+		assert_eq!(format!("{:#}", config), "Loading: Cannot find config file");
+
+		assert_eq!(ErrorKind::of(&anyhow!(Interrupted)), ErrorKind::Interrupted);
+
+		let source = |path: &str, error: anyhow::Error| SourceError {
+			path: path.into(),
+			error,
+		};
+		let same = SourceErrors(vec![
+			source("a", KeyLookUpError::new("a").into()),
+			source("b", KeyLookUpError::new("b").into()),
+		]);
+		assert_eq!(ErrorKind::of(&same.into()), ErrorKind::MissingKey);
+
+		let mixed = SourceErrors(vec![
+			source("a", KeyLookUpError::new("a").into()),
+			source("b", io::Error::from(io::ErrorKind::NotFound).into()),
+		]);
+		assert_eq!(ErrorKind::of(&mixed.into()), ErrorKind::MissingKey);
+
+		assert_eq!(ErrorKind::of(&anyhow!("something")), ErrorKind::Other);
+
+		let member = |name: &str, error: anyhow::Error| MemberError {
+			name: name.into(),
+			error,
+		};
+		let workspace = anyhow!(WorkspaceError {
+			failed: vec![
+				member("a", KeyLookUpError::new("a").into()),
+				member("b", ConfigError::new("Invalid").into()),
+			],
+			total: 3,
+		});
+		assert_eq!(ErrorKind::of(&workspace), ErrorKind::Config);
+		assert_eq!(
+			workspace.to_string(),
+			"2 of 3 workspace members failed to build: a, b\n\n`a`: Cannot find `a` key in the template pool\n\n`b`: Invalid"
+		);
+		assert_eq!(ErrorKind::Other.max(ErrorKind::Io), ErrorKind::Io);
+	}
+
+	#[test]
+	fn exit_codes_are_distinct() {
+		let kinds = [
+			ErrorKind::Config,
+			ErrorKind::MissingKey,
+			ErrorKind::Io,
+			ErrorKind::Collision,
+			ErrorKind::Interrupted,
+			ErrorKind::Other,
+		];
+		let mut codes: Vec<_> = kinds.iter().map(|kind| kind.exit_code()).collect();
+		codes.sort_unstable();
+		codes.dedup();
+
+		assert_eq!(codes.len(), kinds.len());
+		assert!(!codes.contains(&0) && !codes.contains(&1) && !codes.contains(&10));
+	}
+}
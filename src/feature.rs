@@ -1,14 +1,166 @@
+use megumax::app::init::Kit;
+use megumax::app::{render, MessageFormat};
+use megumax::toml::overrides::Override;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 /// Easy to use templating CLI
 #[derive(Debug, StructOpt)]
 pub struct Command {
-	/// Path to the config file
-	#[structopt(long, short, parse(from_os_str), default_value = "megu.toml")]
-	pub config: PathBuf,
+	#[structopt(flatten)]
+	pub global: GlobalOptions,
+
+	/// Builds the project when omitted
+	#[structopt(subcommand)]
+	pub command: Option<Subcommand>,
+}
+
+// Options accepted by every subcommand, either before or after its name. Not a doc comment, structopt would
+// use it as the description of the whole program.
+#[derive(Debug, StructOpt)]
+pub struct GlobalOptions {
+	/// Path to the config file [default: `megu.toml` in this or any parent directory]
+	#[structopt(long, short, global = true, parse(from_os_str))]
+	pub config: Option<PathBuf>,
 
 	/// No output printed to stdout
-	#[structopt(long, short)]
+	#[structopt(long, short, global = true)]
 	pub quiet: bool,
+
+	/// Clear the output directory even if it wasn't created by megumax
+	#[structopt(long, global = true)]
+	pub force: bool,
+
+	/// Show what would change without writing or deleting anything
+	#[structopt(long, global = true)]
+	pub dry_run: bool,
+
+	/// Carry on past failing sources and report every error at the end
+	#[structopt(long, global = true)]
+	pub keep_going: bool,
+
+	/// List the time spent on every source at the end of a build, instead of only the slowest ones
+	#[structopt(long, global = true)]
+	pub timings: bool,
+
+	/// Format of the printed messages
+	#[structopt(
+		long,
+		global = true,
+		value_name = "FORMAT",
+		default_value = "human",
+		possible_values = MessageFormat::VARIANTS
+	)]
+	pub message_format: MessageFormat,
+
+	/// Override a config value, e.g. `--set build.output=/tmp/out`
+	#[structopt(
+		long = "set",
+		global = true,
+		value_name = "KEY=VALUE",
+		number_of_values = 1,
+		parse(try_from_str = Override::from_set)
+	)]
+	pub set: Vec<Override>,
+
+	/// Define a `[keys]` entry, or a comma-separated `[template]` pool when one with that name exists
+	#[structopt(
+		short = "D",
+		global = true,
+		value_name = "NAME=VALUE",
+		number_of_values = 1,
+		parse(try_from_str = Override::from_define)
+	)]
+	pub define: Vec<Override>,
+
+	/// Only use these workspace members, by directory name or path relative to the workspace root
+	#[structopt(
+		long,
+		short,
+		global = true,
+		value_name = "MEMBER",
+		number_of_values = 1
+	)]
+	pub package: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Subcommand {
+	/// Render the project into the output directory, the default
+	Build,
+	/// List every output the build would produce, same as `build --dry-run`
+	Plan,
+	/// Remove the files megumax generated, leaving preserved and unrelated files alone
+	Clean,
+	/// Show how a fresh build would differ from the output directory, exiting with 1 when it does
+	Diff,
+	/// Fail with exit code 10 when the output directory isn't exactly what a fresh build would produce
+	Verify,
+	/// Print a single source rendered with the config's keys and pools
+	Render {
+		/// Source file to render, `-` reads it from stdin
+		#[structopt(parse(from_os_str))]
+		file: PathBuf,
+
+		/// Pick the value of a pool, e.g. `--var color=red`
+		#[structopt(
+			long = "var",
+			value_name = "NAME=VALUE",
+			number_of_values = 1,
+			parse(try_from_str = render::parse_var)
+		)]
+		vars: Vec<(String, String)>,
+	},
+	/// List every output a source produces, with the variant values and `[nth]` of each
+	Explain {
+		/// Source file, may contain template keys like `src/[color].md`
+		#[structopt(parse(from_os_str))]
+		file: PathBuf,
+	},
+	/// Find the source and variant that produce an output file
+	Why {
+		/// Output file, like `build/red.md`
+		#[structopt(parse(from_os_str))]
+		output: PathBuf,
+	},
+	/// Check the project for mistakes, running every check when none is selected
+	Lint {
+		/// Report unused keys and pools, undefined placeholders and where each key is referenced
+		#[structopt(long)]
+		usage: bool,
+	},
+	/// Build, then rebuild the sources that change and reload the config when it changes, until Ctrl-C
+	Watch,
+	/// Create `megu.toml`, the source directory and a `.gitignore` entry in a new project
+	Init {
+		/// Directory of the new project
+		#[structopt(parse(from_os_str), default_value = ".")]
+		dir: PathBuf,
+
+		/// Project name, used by starter kits [default: the directory name]
+		#[structopt(long)]
+		name: Option<String>,
+
+		/// Source directory, asked for when omitted in a terminal [default: src/]
+		#[structopt(long)]
+		src: Option<String>,
+
+		/// Output directory, asked for when omitted in a terminal [default: build/]
+		#[structopt(long)]
+		output: Option<String>,
+
+		/// Starter kit to create the project from
+		#[structopt(long, value_name = "KIT", possible_values = Kit::VARIANTS)]
+		kit: Option<Kit>,
+
+		/// Use the defaults instead of asking
+		#[structopt(long, short)]
+		yes: bool,
+	},
+	/// Upgrade the config file, and those of the workspace members, to the latest format, keeping comments and formatting
+	Migrate {
+		/// Write the changes even though `--quiet` hides them
+		#[structopt(long, short)]
+		yes: bool,
+	},
 }
@@ -1,6 +1,8 @@
 pub mod app;
 pub mod config;
 pub mod core;
+pub mod error;
 pub mod share;
 pub mod toml;
 pub mod utils;
+pub mod workspace;
@@ -1,37 +1,274 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use colorful::*;
-use flexi_logger::{style, Level, Logger};
+use flexi_logger::writers::LogWriter;
+use flexi_logger::{style, DeferredNow, Duplicate, Level, LogTarget, Logger, Record};
+use megumax::app::event::Event;
+use megumax::app::report::{self, Reporter};
+use megumax::app::MessageFormat;
+use megumax::config::Config;
+use megumax::error::ErrorKind;
+use megumax::toml::overrides::Overrides;
+use megumax::workspace::{single_project, Workspace};
 use megumax::{app, config};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
 use structopt::StructOpt;
 
 mod feature;
-use feature::Command;
+use feature::{Command, Subcommand};
+
+/// Exit code of `megu diff` when the output directory differs from a fresh build.
+const DIFFERENCES_EXIT_CODE: i32 = 1;
+
+/// Exit code of `megu lint` when it reports problems.
+const FINDINGS_EXIT_CODE: i32 = 1;
+
+/// Exit code of `megu verify` when the output directory is stale.
+const STALE_EXIT_CODE: i32 = 10;
 
 fn main() {
 	let opts = Command::from_args();
+	let global = &opts.global;
+	let mut reporter = report::for_format(global.message_format, global.quiet, global.timings);
 
-	if let Err(err) = run(opts) {
-		eprintln!("{} {:#}", "⚠".red(), err);
+	if let Err(err) = run(opts, reporter.as_mut()) {
+		reporter.error(&err);
+		std::process::exit(ErrorKind::of(&err).exit_code());
 	}
 }
 
-pub fn run(opts: Command) -> Result<()> {
-	if !opts.quiet {
-		init_logger().unwrap();
+pub fn run(opts: Command, reporter: &mut dyn Reporter) -> Result<()> {
+	let Command { global, command } = opts;
+
+	if !global.quiet {
+		init_logger(global.message_format).unwrap();
+	}
+
+	let command = command.unwrap_or(Subcommand::Build);
+
+	// There is no config to discover before the project is created.
+	if let Subcommand::Init {
+		dir,
+		name,
+		src,
+		output,
+		kit,
+		yes,
+	} = command
+	{
+		let options = app::init::InitOptions {
+			dir,
+			name,
+			src,
+			output,
+			kit,
+			interactive: !yes && std::io::stdin().is_terminal(),
+		};
+		return app::init::init_project(&options);
 	}
 
-	let config = config::load_config(&opts.config)?;
-	app::build_project(&config)
+	let path = match global.config {
+		Some(path) => path,
+		None => config::discover_config()?,
+	};
+
+	// Only builds stop at the next file on Ctrl-C, the other commands are still killed right away.
+	if matches!(
+		command,
+		Subcommand::Build | Subcommand::Plan | Subcommand::Watch
+	) {
+		app::interrupt::install()?;
+	}
+
+	// Command line takes precedence over environment variables.
+	let overrides = Overrides::from_env().with(global.set).with(global.define);
+	let options = app::BuildOptions {
+		force: global.force,
+		dry_run: global.dry_run || matches!(command, Subcommand::Plan),
+		message_format: global.message_format,
+		changed: None,
+		keep_going: global.keep_going,
+	};
+
+	match command {
+		Subcommand::Build | Subcommand::Plan => {
+			build(&path, &overrides, &global.package, &options, reporter)?;
+		}
+		Subcommand::Watch => {
+			let path = single_project(&path, &global.package)?;
+			let load = || load_project(&path, &overrides);
+			app::watch::watch_project(&path, load, &options, reporter)?;
+		}
+		Subcommand::Clean => {
+			for config in load_projects(&path, &overrides, &global.package)? {
+				app::clean::clean_project(&config, &options)?;
+			}
+		}
+		Subcommand::Diff => {
+			let mut changed = false;
+			let stdout = std::io::stdout();
+
+			for config in load_projects(&path, &overrides, &global.package)? {
+				changed |= app::diff::diff_project(&config, stdout.lock())?;
+			}
+
+			if changed {
+				std::process::exit(DIFFERENCES_EXIT_CODE);
+			}
+		}
+		Subcommand::Verify => {
+			let mut up_to_date = true;
+
+			for config in load_projects(&path, &overrides, &global.package)? {
+				up_to_date &= app::verify::verify_project(&config)?;
+			}
+
+			if !up_to_date {
+				std::process::exit(STALE_EXIT_CODE);
+			}
+		}
+		Subcommand::Render { file, vars } => {
+			let config = load_project(&single_project(&path, &global.package)?, &overrides)?;
+			let stdout = std::io::stdout();
+			app::render::render_file(&config, &file, &vars, stdout.lock())?;
+		}
+		Subcommand::Explain { file } => {
+			let config = load_project(&single_project(&path, &global.package)?, &overrides)?;
+			app::explain::explain(&config, &file, options.message_format)?;
+		}
+		Subcommand::Why { output } => {
+			let config = load_project(&single_project(&path, &global.package)?, &overrides)?;
+			app::explain::why(&config, &output, options.message_format)?;
+		}
+		// Key usage is the only check so far, so it runs whether `--usage` is given or not.
+		Subcommand::Lint { usage: _ } => {
+			let mut clean = true;
+
+			for config in load_projects(&path, &overrides, &global.package)? {
+				clean &= app::lint::lint_usage(&config, options.message_format)?;
+			}
+
+			if !clean {
+				std::process::exit(FINDINGS_EXIT_CODE);
+			}
+		}
+		Subcommand::Init { .. } => unreachable!("`megu init` runs before loading the config"),
+		Subcommand::Migrate { yes } => {
+			// Nobody sees the diff with `--quiet`, so the files are only written once confirmed.
+			let confirmed = yes || !global.quiet;
+			let mut unwritten = 0;
+
+			for path in app::migrate::config_files(&path, &global.package)? {
+				let migrated = app::migrate::migrate_config(&path, options.dry_run || !confirmed)?;
+				if migrated.is_changed() && !options.dry_run && !confirmed {
+					unwritten += 1;
+				}
+			}
+
+			if unwritten > 0 {
+				bail!(
+					"{} config files are outdated, pass `--yes` to upgrade them without showing the changes",
+					unwritten
+				);
+			}
+		}
+	}
+
+	Ok(())
 }
 
-fn init_logger() -> Result<()> {
-	Logger::with_str("megumax=info")
-		.format(|w, _, record| match record.level() {
-			Level::Info => write!(w, "{}", record.args()),
-			Level::Error => write!(w, "{} {}", "⚠".red(), record.args()),
-			level => write!(w, "[{}] {}", style(level, level), record.args()),
-		})
-		.start()
-		.with_context(|| "Initializing logger")?;
+fn build(
+	path: &Path,
+	overrides: &Overrides,
+	packages: &[String],
+	options: &app::BuildOptions,
+	reporter: &mut dyn Reporter,
+) -> Result<()> {
+	if let Some(workspace) = Workspace::load(path)? {
+		let members = workspace.select(packages)?;
+		return app::workspace::build_workspace(&workspace, &members, overrides, options, reporter);
+	}
+
+	let config = load_project(&single_project(path, packages)?, overrides)?;
+	app::build_project(&config, options, reporter)
+}
+
+/// Load the selected members of the workspace at `path`, or the project at `path` itself.
+fn load_projects(path: &Path, overrides: &Overrides, packages: &[String]) -> Result<Vec<Config>> {
+	match Workspace::load(path)? {
+		Some(workspace) => workspace
+			.select(packages)?
+			.into_iter()
+			.map(|member| workspace.load_member(&member.config_path, overrides))
+			.collect(),
+		None => Ok(vec![load_project(
+			&single_project(path, packages)?,
+			overrides,
+		)?]),
+	}
+}
+
+/// Load the project at `path`, inheriting from the workspace it's a member of.
+fn load_project(path: &Path, overrides: &Overrides) -> Result<Config> {
+	match Workspace::find_parent(path)? {
+		Some(workspace) => workspace.load_member(path, overrides),
+		None => config::load_config_with(path, overrides),
+	}
+}
+
+fn init_logger(format: MessageFormat) -> Result<()> {
+	let logger = Logger::with_env_or_str("megumax=info").format(human_format);
+
+	// Warnings and errors also become events on stdout, next to the ones of the build.
+	let logger = match format {
+		MessageFormat::Human => logger.log_target(LogTarget::Writer(Box::new(StderrWriter))),
+		MessageFormat::Json => logger
+			.log_target(LogTarget::DevNull)
+			.duplicate_to_stderr(Duplicate::Info)
+			.duplicate_to_stdout(Duplicate::Warn)
+			.format_for_stdout(json_format),
+	};
+
+	logger.start().with_context(|| "Initializing logger")?;
 	Ok(())
 }
+
+/// Writes log messages to stderr, hiding the progress bar of the build while doing so.
+struct StderrWriter;
+
+impl LogWriter for StderrWriter {
+	fn write(&self, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+		report::suspend(|| {
+			let stderr = io::stderr();
+			let mut stderr = stderr.lock();
+			human_format(&mut stderr, now, record)?;
+			writeln!(stderr)
+		})
+	}
+
+	fn flush(&self) -> io::Result<()> {
+		io::stderr().flush()
+	}
+
+	fn max_log_level(&self) -> log::LevelFilter {
+		log::LevelFilter::Trace
+	}
+}
+
+fn human_format(w: &mut dyn Write, _: &mut DeferredNow, record: &Record) -> io::Result<()> {
+	match record.level() {
+		Level::Info => write!(w, "{}", record.args()),
+		Level::Error => write!(w, "{} {}", "⚠".red(), record.args()),
+		level => write!(w, "[{}] {}", style(level, level), record.args()),
+	}
+}
+
+fn json_format(w: &mut dyn Write, _: &mut DeferredNow, record: &Record) -> io::Result<()> {
+	let message = record.args().to_string();
+	let event = match record.level() {
+		Level::Error => Event::Error { message },
+		_ => Event::Warning { message },
+	};
+
+	write!(w, "{}", serde_json::to_string(&event)?)
+}
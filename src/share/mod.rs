@@ -9,7 +9,20 @@ mod imports;
 pub use imports::*;
 
 use anyhow::{Context, Result};
-use std::path::{Path, PathBuf};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Print `line` to stdout, a reader that went away, like `head` after it has enough lines, isn't an error.
+pub fn print_line(line: &str) -> Result<()> {
+	let stdout = io::stdout();
+	let mut stdout = stdout.lock();
+	let result = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
+
+	match result {
+		Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+		result => result.with_context(|| "Writing to stdout"),
+	}
+}
 
 pub fn stringify_path<P: AsRef<Path>>(path: &P) -> Result<&str> {
 	let path = path.as_ref();
@@ -31,3 +44,83 @@ pub fn replace_prefix<P: AsRef<Path>>(path: P, from: P, to: P) -> Result<PathBuf
 	let result = to.join(path);
 	Ok(result)
 }
+
+/// Turn `path` into an absolute path, resolving `.` and `..` without requiring it to exist.
+pub fn absolute<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+	let path = path.as_ref();
+	let path = if path.is_absolute() {
+		path.to_path_buf()
+	} else {
+		let current_dir = std::env::current_dir().with_context(|| "Reading current directory")?;
+		current_dir.join(path)
+	};
+
+	let mut result = PathBuf::new();
+
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				result.pop();
+			}
+			component => result.push(component),
+		}
+	}
+
+	// Follow symlinks when possible so two spellings of the same directory compare equal.
+	Ok(result.canonicalize().unwrap_or(result))
+}
+
+/// Every file below `root`, relative to `root`.
+pub fn list_files(root: &Path) -> Result<Vec<PathBuf>> {
+	let mut result = Vec::new();
+	list_in(root, root, &mut result)?;
+	Ok(result)
+}
+
+fn list_in(root: &Path, dir: &Path, result: &mut Vec<PathBuf>) -> Result<()> {
+	for entry in std::fs::read_dir(dir).with_context(|| format!("Reading `{}`", dir.display()))? {
+		let path = entry?.path();
+
+		if path.is_dir() {
+			list_in(root, &path, result)?;
+		} else {
+			result.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+		}
+	}
+
+	Ok(())
+}
+
+/// Delete every file below `root` except those `keep` accepts, along with the directories left empty.
+///
+/// `keep` receives paths relative to `root`. Returns whether `root` itself ended up empty.
+pub fn remove_all_except<F: Fn(&Path) -> bool>(root: &Path, keep: F) -> Result<bool> {
+	remove_in(root, root, &keep)
+}
+
+fn remove_in(root: &Path, dir: &Path, keep: &dyn Fn(&Path) -> bool) -> Result<bool> {
+	let mut empty = true;
+
+	for entry in std::fs::read_dir(dir).with_context(|| format!("Reading `{}`", dir.display()))? {
+		let path = entry?.path();
+		let relative = path.strip_prefix(root).unwrap_or(&path);
+
+		if keep(relative) {
+			empty = false;
+		} else if path.is_dir() {
+			if remove_in(root, &path, keep)? {
+				std::fs::remove_dir(&path)
+					.with_context(|| format!("Removing `{}`", path.display()))?;
+			} else {
+				empty = false;
+			}
+		} else {
+			log::debug!("Remove {}", path.display());
+			std::fs::remove_file(&path)
+				.with_context(|| format!("Removing `{}`", path.display()))?;
+		}
+	}
+
+	Ok(empty)
+}
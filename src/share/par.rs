@@ -33,3 +33,28 @@ pub async fn open_file<P: AsRef<Path>>(path: P) -> Result<File> {
 		.with_context(|| "Opening File reader")
 		.with_context(|| format!("At: {}", path.display()))
 }
+
+pub async fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+	let path = path.as_ref();
+	fs::read(path)
+		.await
+		.with_context(|| "Reading File content")
+		.with_context(|| format!("At: {}", path.display()))
+}
+
+/// Whether the file at `path` already has exactly `content`.
+pub async fn is_unchanged<P: AsRef<Path>>(path: P, content: &[u8]) -> bool {
+	match fs::read(path).await {
+		Ok(existing) => existing == content,
+		Err(_) => false,
+	}
+}
+
+pub async fn write_file<P: AsRef<Path>>(path: P, content: &[u8]) -> Result<()> {
+	let path = path.as_ref();
+	ensure_parent(path).await?;
+	fs::write(path, content)
+		.await
+		.with_context(|| "Writing File content")
+		.with_context(|| format!("At: {}", path.display()))
+}
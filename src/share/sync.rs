@@ -18,7 +18,7 @@ pub fn ensure_parent<P: AsRef<Path>>(path: P) -> Result<()> {
 
 pub fn create_file<P: AsRef<Path>>(path: P) -> Result<File> {
 	let path = path.as_ref();
-	ensure_parent(&path)?;
+	ensure_parent(path)?;
 	File::create(path)
 		.with_context(|| "Creating File writer")
 		.with_context(|| format!("At: {}", path.display()))
@@ -26,7 +26,30 @@ pub fn create_file<P: AsRef<Path>>(path: P) -> Result<File> {
 
 pub fn open_file<P: AsRef<Path>>(path: P) -> Result<File> {
 	let path = path.as_ref();
-	File::open(&path)
+	File::open(path)
 		.with_context(|| "Opening File reader")
 		.with_context(|| format!("At: {}", path.display()))
 }
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+	let path = path.as_ref();
+	fs::read(path)
+		.with_context(|| "Reading File content")
+		.with_context(|| format!("At: {}", path.display()))
+}
+
+/// Whether the file at `path` already has exactly `content`.
+pub fn is_unchanged<P: AsRef<Path>>(path: P, content: &[u8]) -> bool {
+	match fs::read(path) {
+		Ok(existing) => existing == content,
+		Err(_) => false,
+	}
+}
+
+pub fn write_file<P: AsRef<Path>>(path: P, content: &[u8]) -> Result<()> {
+	let path = path.as_ref();
+	ensure_parent(path)?;
+	fs::write(path, content)
+		.with_context(|| "Writing File content")
+		.with_context(|| format!("At: {}", path.display()))
+}
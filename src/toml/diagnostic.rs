@@ -0,0 +1,180 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Location of a problem inside the config file, zero-indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+	pub line: usize,
+	pub column: usize,
+	pub len: usize,
+}
+
+impl Span {
+	pub fn new(line: usize, column: usize, len: usize) -> Self {
+		Self { line, column, len }
+	}
+}
+
+/// A single problem found while reading the config file.
+///
+/// When a span is available the offending line is rendered as a code frame.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+	pub message: String,
+	pub help: Option<String>,
+	pub path: PathBuf,
+	pub span: Option<Span>,
+	pub snippet: Option<String>,
+}
+
+impl Diagnostic {
+	pub fn new(message: impl Into<String>, path: &Path) -> Self {
+		Self {
+			message: message.into(),
+			help: None,
+			path: path.to_path_buf(),
+			span: None,
+			snippet: None,
+		}
+	}
+
+	pub fn with_help(mut self, help: impl Into<String>) -> Self {
+		self.help = Some(help.into());
+		self
+	}
+
+	pub fn with_span(mut self, span: Option<Span>, content: &str) -> Self {
+		self.snippet = span.and_then(|span| content.lines().nth(span.line).map(str::to_owned));
+		self.span = span;
+		self
+	}
+
+	/// Convert an error from the `toml` crate, keeping its position.
+	pub fn from_toml(error: &toml::de::Error, path: &Path, content: &str) -> Self {
+		let message = error.to_string();
+		// The location is rendered by the code frame instead.
+		let message = match message.rfind(" at line ") {
+			Some(n) => message[..n].to_owned(),
+			None => message,
+		};
+		let span = error
+			.line_col()
+			.map(|(line, column)| Span::new(line, column, 1));
+		Self::new(message, path).with_span(span, content)
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.message)?;
+
+		match (self.span, &self.snippet) {
+			(Some(span), Some(snippet)) => {
+				let number = (span.line + 1).to_string();
+				let gutter = " ".repeat(number.len());
+				let marker = "^".repeat(span.len.max(1));
+				writeln!(f)?;
+				writeln!(
+					f,
+					"{}--> {}:{}:{}",
+					gutter,
+					self.path.display(),
+					number,
+					span.column + 1
+				)?;
+				writeln!(f, "{} |", gutter)?;
+				writeln!(f, "{} | {}", number, snippet)?;
+				write!(f, "{} | {}{}", gutter, " ".repeat(span.column), marker)?;
+			}
+			_ => write!(f, "\n  --> {}", self.path.display())?,
+		}
+
+		if let Some(help) = &self.help {
+			write!(f, "\n  = help: {}", help)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Every problem found in a config file, reported together.
+#[derive(Debug, Error)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let count = self.0.len();
+		let noun = if count == 1 { "problem" } else { "problems" };
+		write!(f, "Found {} {} in the config file", count, noun)?;
+
+		for diagnostic in &self.0 {
+			write!(f, "\n\n{}", diagnostic)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl From<Diagnostic> for Diagnostics {
+	fn from(diagnostic: Diagnostic) -> Self {
+		Self(vec![diagnostic])
+	}
+}
+
+/// Pick the closest candidate to `input`, if any is close enough to be a likely typo.
+pub fn suggest<'a>(input: &str, candidates: &[&'a str]) -> Option<&'a str> {
+	candidates
+		.iter()
+		.map(|candidate| (strsim::jaro_winkler(input, candidate), *candidate))
+		.filter(|(score, _)| *score > 0.8)
+		.max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+		.map(|(_, candidate)| candidate)
+}
+
+/// Find where `key` is defined, either as a `[table]` header or as a `key = ...` entry inside `table`.
+///
+/// `toml` doesn't keep the position of values, so this scans the raw content instead.
+pub fn locate(content: &str, table: Option<&str>, key: &str) -> Option<Span> {
+	let mut current: Option<String> = None;
+
+	for (line, text) in content.lines().enumerate() {
+		let trimmed = text.trim_start();
+		let column = text.len() - trimmed.len();
+
+		if let Some(header) = parse_header(trimmed) {
+			if table.is_none() && header.split('.').next() == Some(key) {
+				let offset = trimmed.find(key).unwrap_or(0);
+				return Some(Span::new(line, column + offset, key.len()));
+			}
+
+			current = Some(header);
+			continue;
+		}
+
+		if current.as_deref() != table {
+			continue;
+		}
+
+		for quote in &["", "\"", "'"] {
+			let quoted = format!("{}{}{}", quote, key, quote);
+			let rest = match trimmed.strip_prefix(quoted.as_str()) {
+				Some(rest) => rest.trim_start(),
+				None => continue,
+			};
+
+			if rest.starts_with('=') || rest.starts_with('.') {
+				return Some(Span::new(line, column, quoted.len()));
+			}
+		}
+	}
+
+	None
+}
+
+fn parse_header(line: &str) -> Option<String> {
+	let line = line.strip_prefix('[')?;
+	let line = line.strip_prefix('[').unwrap_or(line);
+	let end = line.find(']')?;
+	Some(line[..end].trim().to_owned())
+}
@@ -0,0 +1,148 @@
+use crate::error::ConfigError;
+use anyhow::{bail, Result};
+use std::convert::TryFrom;
+use toml_edit::{value, DocumentMut};
+
+/// Version of the config format understood by this release.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// A single upgrade step, turning a config of version `to - 1` into version `to`.
+pub struct Migration {
+	pub to: u32,
+	pub description: &'static str,
+	apply: fn(&mut DocumentMut),
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+	to: 1,
+	description: "Add the `version` field",
+	apply: add_version,
+}];
+
+/// The result of upgrading a config file.
+#[derive(Debug)]
+pub struct Migrated {
+	pub from: u32,
+	pub to: u32,
+	pub steps: Vec<&'static str>,
+	pub content: String,
+}
+
+impl Migrated {
+	pub fn is_changed(&self) -> bool {
+		self.from != self.to
+	}
+}
+
+/// Upgrade `content` to [`CURRENT_VERSION`], keeping comments and formatting of everything else.
+pub fn migrate(content: &str) -> Result<Migrated> {
+	let mut document: DocumentMut = content
+		.parse()
+		.map_err(|e| ConfigError::new(format!("Cannot parse config file: {}", e)))?;
+	let from = document_version(&document)?;
+
+	if from > CURRENT_VERSION {
+		bail!(ConfigError::new(format!(
+			"Config version {} is newer than the latest supported version {}",
+			from, CURRENT_VERSION
+		)));
+	}
+
+	let mut steps = Vec::new();
+
+	for migration in MIGRATIONS.iter().filter(|m| m.to > from) {
+		(migration.apply)(&mut document);
+		document["version"] = value(i64::from(migration.to));
+		steps.push(migration.description);
+	}
+
+	let content = if steps.is_empty() {
+		content.to_owned()
+	} else {
+		document.to_string()
+	};
+
+	Ok(Migrated {
+		from,
+		to: CURRENT_VERSION,
+		steps,
+		content,
+	})
+}
+
+fn document_version(document: &DocumentMut) -> Result<u32> {
+	match document.get("version") {
+		None => Ok(0),
+		Some(item) => item
+			.as_integer()
+			.and_then(|n| u32::try_from(n).ok())
+			.ok_or_else(|| ConfigError::new("`version` must be a positive integer").into()),
+	}
+}
+
+/// Configs written before versioning was introduced don't have a `version` field.
+fn add_version(document: &mut DocumentMut) {
+	// Keep `version` above every table, separated by an empty line.
+	document.insert("version", value(1));
+
+	if let Some(mut key) = document.key_mut("version") {
+		key.leaf_decor_mut().set_prefix("");
+	}
+
+	let first_table = document
+		.iter_mut()
+		.find_map(|(_, item)| item.as_table_mut().filter(|t| !t.is_implicit()));
+
+	if let Some(table) = first_table {
+		let decor = table.decor_mut();
+		let prefix = decor
+			.prefix()
+			.and_then(|s| s.as_str())
+			.unwrap_or_default()
+			.to_owned();
+		decor.set_prefix(format!("\n{}", prefix));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::ErrorKind;
+
+	#[test]
+	fn add_version_field() {
+		let content = "# My project\n[build]\noutput = \"out\" # comment\n";
+		let result = migrate(content).unwrap();
+
+		assert_eq!(result.from, 0);
+		assert_eq!(result.to, CURRENT_VERSION);
+		assert_eq!(
+			result.content,
+			"version = 1\n\n# My project\n[build]\noutput = \"out\" # comment\n"
+		);
+	}
+
+	#[test]
+	fn already_current() {
+		let content = "version = 1\n\n[build]\n";
+		let result = migrate(content).unwrap();
+
+		assert!(!result.is_changed());
+		assert_eq!(result.content, content);
+	}
+
+	#[test]
+	fn newer_version() {
+		let content = "version = 99\n";
+		assert!(migrate(content).is_err());
+	}
+
+	#[test]
+	fn invalid_config() {
+		let err = migrate("[build\n").unwrap_err();
+		assert_eq!(ErrorKind::of(&err), ErrorKind::Config);
+
+		let err = migrate("version = \"one\"\n").unwrap_err();
+		assert_eq!(ErrorKind::of(&err), ErrorKind::Config);
+	}
+}
@@ -1,52 +1,188 @@
-use super::config::{resolve_symbol, Config, ConfigBuilder};
+use super::config::{
+	resolve_symbol, CleanMode, Config, ConfigBuilder, GlobList, MergeRule, MergeStrategy,
+};
+use anyhow::{Context, Result};
+use globset::Glob;
 use megumax_template::{Pool, Template};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub mod consts;
+pub mod diagnostic;
+pub mod migrate;
+pub mod overrides;
+mod schema;
+
+use diagnostic::{Diagnostic, Diagnostics};
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigFormat {
+	/// Configs without a version predate versioning and are treated as version 0.
+	#[serde(default)]
+	pub version: u32,
 	#[serde(default)]
 	pub template: TemplateFormat,
 	#[serde(default)]
 	pub keys: KeyFormat,
-	pub build: BuildFormat,
+	/// Only optional for a workspace root that doesn't build anything itself.
+	pub build: Option<BuildFormat>,
+	pub workspace: Option<WorkspaceFormat>,
 }
 
 impl ConfigFormat {
-	pub fn compile(self, path: PathBuf) -> Config {
+	/// Parse the content of a config file, reporting every problem with its location in `path`.
+	pub fn parse(content: &str, path: &Path) -> Result<Self, Diagnostics> {
+		let value: toml::Value =
+			toml::from_str(content).map_err(|e| Diagnostic::from_toml(&e, path, content))?;
+
+		let problems = schema::validate(&value, path, content);
+		if !problems.is_empty() {
+			return Err(Diagnostics(problems));
+		}
+
+		let format =
+			toml::from_str(content).map_err(|e| Diagnostic::from_toml(&e, path, content))?;
+		Ok(format)
+	}
+
+	pub fn compile(self, path: PathBuf) -> Result<Config> {
 		log::debug!("Compile config format...");
-		let (src, dest) = self.build.compile();
+
+		// Older configs still build as they are, `megu migrate` reports what upgrading them would change.
+		if self.version < migrate::CURRENT_VERSION {
+			log::debug!(
+				"Config version {} is older than {}",
+				self.version,
+				migrate::CURRENT_VERSION
+			);
+		}
+
+		let base = path.parent().unwrap_or_else(|| Path::new(""));
+		let build = self.build.unwrap_or_default();
+		let clean = build.clean;
+		let preserve = build.compile_preserve()?;
+		let merge = build.compile_merge()?;
+		let (src, dest) = build.compile(base);
 		let template = self.template.compile();
 		let keys = self.keys.compile();
 
-		ConfigBuilder::new(src, dest, path)
+		let config = ConfigBuilder::new(src, dest, path)
 			.with_template(template)
 			.with_keys(keys)
-			.build()
+			.with_clean(clean)
+			.with_preserve(preserve)
+			.with_merge(merge)
+			.build();
+
+		// Builds check it again before deleting anything, in case the config was built by hand.
+		config.check_dest()?;
+		Ok(config)
+	}
+
+	/// Fill in `[keys]` and `[template]` entries shared by the workspace root, without replacing the member's own.
+	pub fn inherit(&mut self, parent: &ConfigFormat) {
+		for (key, value) in &parent.keys.0 {
+			self.keys
+				.0
+				.entry(key.clone())
+				.or_insert_with(|| value.clone());
+		}
+
+		for (key, value) in &parent.template.0 {
+			self.template
+				.0
+				.entry(key.clone())
+				.or_insert_with(|| value.clone());
+		}
 	}
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BuildFormat {
 	#[serde(default = "consts::output_dir")]
 	pub output: PathBuf,
 	#[serde(default = "consts::current_dir")]
 	pub src: PathBuf,
+	#[serde(default)]
+	pub clean: CleanMode,
+	/// Glob patterns of files in the output directory that are never deleted.
+	#[serde(default)]
+	pub preserve: Vec<String>,
+	/// How outputs written by several sources or variants are combined.
+	#[serde(default)]
+	pub merge: Vec<MergeFormat>,
+}
+
+impl Default for BuildFormat {
+	fn default() -> Self {
+		Self {
+			output: consts::output_dir(),
+			src: consts::current_dir(),
+			clean: CleanMode::default(),
+			preserve: Vec::new(),
+			merge: Vec::new(),
+		}
+	}
 }
 
 impl BuildFormat {
-	fn compile(self) -> (PathBuf, PathBuf) {
-		let src = resolve_symbol(self.src);
+	fn compile_preserve(&self) -> Result<GlobList> {
+		let globs = self
+			.preserve
+			.iter()
+			.map(|pattern| {
+				Glob::new(pattern)
+					.with_context(|| format!("Invalid pattern `{}` in `build.preserve`", pattern))
+			})
+			.collect::<Result<_>>()?;
+
+		GlobList::new(globs)
+	}
+
+	fn compile_merge(&self) -> Result<Vec<MergeRule>> {
+		self.merge
+			.iter()
+			.map(|merge| {
+				let glob = Glob::new(&merge.files).with_context(|| {
+					format!("Invalid pattern `{}` in `build.merge`", merge.files)
+				})?;
+
+				Ok(MergeRule {
+					files: glob.compile_matcher(),
+					strategy: merge.strategy,
+				})
+			})
+			.collect()
+	}
+
+	/// Resolve `src` and `output` relative to `base`, the directory containing the config file.
+	fn compile(self, base: &Path) -> (PathBuf, PathBuf) {
+		let src = base.join(resolve_symbol(self.src));
 		log::debug!("Resolve source path into {:?}", src);
-		let build = resolve_symbol(self.output);
+		let build = base.join(resolve_symbol(self.output));
 		log::debug!("Resolve build path into {:?}", build);
 		(src, build)
 	}
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeFormat {
+	/// Glob pattern of outputs relative to the output directory.
+	pub files: String,
+	pub strategy: MergeStrategy,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceFormat {
+	/// Directories of member projects relative to the workspace root, glob patterns are allowed.
+	pub members: Vec<String>,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TemplateFormat(HashMap<String, Vec<String>>);
 
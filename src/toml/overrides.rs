@@ -0,0 +1,418 @@
+use super::diagnostic::suggest;
+use super::schema::fields;
+use super::ConfigFormat;
+use crate::config::CleanMode;
+use crate::error::ConfigError;
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const ENV_PREFIX: &str = "MEGU_";
+
+/// Config sections that can be overridden, `MEGU_*` variables naming any other section are ignored.
+const SECTIONS: &[&str] = &["build", "keys", "template"];
+
+/// `[build]` fields that can be overridden, `merge` is a list of tables that has no single value to set.
+const BUILD_FIELDS: &[&str] = &["output", "src", "clean", "preserve"];
+
+/// Where the effective value of a config entry came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+	File,
+	Env(String),
+	Cli(&'static str),
+}
+
+impl fmt::Display for Source {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Source::File => write!(f, "config file"),
+			Source::Env(name) => write!(f, "environment variable `{}`", name),
+			Source::Cli(flag) => write!(f, "command line `{}`", flag),
+		}
+	}
+}
+
+/// Which part of the config an override is aimed at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+	/// A dotted path like `build.output`, `keys.foo` or `template.color`.
+	Path(String),
+	/// A `-D` definition, which goes to `[template]` if a pool with that name exists and to `[keys]` otherwise.
+	Define(String),
+}
+
+impl fmt::Display for Target {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Target::Path(path) => write!(f, "{}", path),
+			Target::Define(name) => write!(f, "{}", name),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Override {
+	pub target: Target,
+	pub value: String,
+	pub source: Source,
+}
+
+impl Override {
+	pub fn new(target: Target, value: impl Into<String>, source: Source) -> Self {
+		Self {
+			target,
+			value: value.into(),
+			source,
+		}
+	}
+
+	/// Parse the `KEY=VALUE` argument of `--set`.
+	pub fn from_set(input: &str) -> Result<Self, String> {
+		let (key, value) = split_assignment(input)?;
+		Ok(Self::new(Target::Path(key), value, Source::Cli("--set")))
+	}
+
+	/// Parse the `NAME=VALUE` argument of `-D`.
+	pub fn from_define(input: &str) -> Result<Self, String> {
+		let (key, value) = split_assignment(input)?;
+		Ok(Self::new(Target::Define(key), value, Source::Cli("-D")))
+	}
+
+	/// Parse an environment variable like `MEGU_BUILD_OUTPUT`, ignoring variables that don't name a section that
+	/// can be overridden.
+	///
+	/// `[keys]` and `[template]` names keep their case, they match existing entries regardless of it when applied.
+	pub fn from_env(name: &str, value: &str) -> Option<Self> {
+		let rest = name.strip_prefix(ENV_PREFIX)?;
+		let (section, key) = rest.split_at(rest.find('_')?);
+		let section = section.to_lowercase();
+		let key = &key[1..];
+
+		if !SECTIONS.contains(&section.as_str()) || key.is_empty() {
+			return None;
+		}
+
+		let key = match section.as_str() {
+			"build" => key.to_lowercase(),
+			_ => key.to_owned(),
+		};
+
+		let path = format!("{}.{}", section, key);
+		let source = Source::Env(name.to_owned());
+		Some(Self::new(Target::Path(path), value, source))
+	}
+}
+
+fn split_assignment(input: &str) -> Result<(String, String), String> {
+	let n = input
+		.find('=')
+		.ok_or_else(|| format!("expected `KEY=VALUE` but got `{}`", input))?;
+	let key = input[..n].trim();
+
+	if key.is_empty() {
+		return Err(format!("missing key in `{}`", input));
+	}
+
+	Ok((key.to_owned(), input[n + 1..].to_owned()))
+}
+
+/// Config values that take precedence over the config file, applied in insertion order.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+	list: Vec<Override>,
+}
+
+impl Overrides {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Collect overrides from every `MEGU_*` environment variable.
+	pub fn from_env() -> Self {
+		let mut list: Vec<_> = std::env::vars()
+			.filter_map(|(name, value)| Override::from_env(&name, &value))
+			.collect();
+		// Make the order independent of the platform.
+		list.sort_by_key(|value| value.target.to_string());
+		Self { list }
+	}
+
+	pub fn with(mut self, overrides: impl IntoIterator<Item = Override>) -> Self {
+		self.list.extend(overrides);
+		self
+	}
+
+	/// Merge every override on top of `format` and log where each value ends up coming from.
+	pub fn apply(&self, format: &mut ConfigFormat) -> Result<()> {
+		let mut sources = BTreeMap::new();
+
+		for value in &self.list {
+			let path = apply_one(format, value)?;
+			sources.insert(path, &value.source);
+		}
+
+		log_sources(format, &sources);
+		Ok(())
+	}
+}
+
+fn apply_one(format: &mut ConfigFormat, value: &Override) -> Result<String> {
+	let path = match &value.target {
+		Target::Path(path) => path.clone(),
+		Target::Define(name) if format.template.0.contains_key(name) => {
+			format!("template.{}", name)
+		}
+		Target::Define(name) => format!("keys.{}", name),
+	};
+
+	let (section, key) = match path.find('.') {
+		Some(n) => (&path[..n], &path[n + 1..]),
+		None => (path.as_str(), ""),
+	};
+
+	let mut resolved = path.clone();
+
+	match (section, key) {
+		("build", "output") => build(format).output = value.value.clone().into(),
+		("build", "src") => build(format).src = value.value.clone().into(),
+		("build", "clean") => {
+			build(format).clean = match value.value.as_str() {
+				"all" => CleanMode::All,
+				"prune" => CleanMode::Prune,
+				other => bail!(ConfigError::new(format!(
+					"Invalid value `{}` for `build.clean` from {}, expected `all` or `prune`",
+					other, value.source
+				))),
+			}
+		}
+		("build", "preserve") => build(format).preserve = split_list(&value.value),
+		("keys", key) if !key.is_empty() => {
+			let key = existing_name(format.keys.0.keys(), key, &value.source);
+			resolved = format!("keys.{}", key);
+			format.keys.0.insert(key, value.value.clone());
+		}
+		("template", key) if !key.is_empty() => {
+			let key = existing_name(format.template.0.keys(), key, &value.source);
+			resolved = format!("template.{}", key);
+			let list = split_list(&value.value);
+			format.template.0.insert(key, list);
+		}
+		("build", key) if !fields::<super::BuildFormat>().contains(&key) => {
+			bail!(ConfigError::new(format!(
+				"Unknown config key `{}` from {}{}",
+				path,
+				value.source,
+				hint(key, BUILD_FIELDS, "build.")
+			)))
+		}
+		(section, _) if section == "build" || fields::<ConfigFormat>().contains(&section) => {
+			bail!(ConfigError::new(format!(
+				"Config key `{}` from {} cannot be overridden, edit it in the config file instead",
+				path, value.source
+			)))
+		}
+		(section, _) => bail!(ConfigError::new(format!(
+			"Unknown config key `{}` from {}{}",
+			path,
+			value.source,
+			hint(section, SECTIONS, "")
+		))),
+	}
+
+	Ok(resolved)
+}
+
+/// Environment variable names are usually upper case, so they pick the entry that only differs in case if there is one.
+fn existing_name<'a>(
+	mut names: impl Iterator<Item = &'a String>,
+	name: &str,
+	source: &Source,
+) -> String {
+	let found = match source {
+		Source::Env(_) => names.find(|existing| existing.eq_ignore_ascii_case(name)),
+		_ => None,
+	};
+
+	found.map_or_else(|| name.to_owned(), String::clone)
+}
+
+fn split_list(value: &str) -> Vec<String> {
+	value.split(',').map(|s| s.trim().to_owned()).collect()
+}
+
+fn build(format: &mut ConfigFormat) -> &mut super::BuildFormat {
+	format.build.get_or_insert_with(Default::default)
+}
+
+fn hint(input: &str, candidates: &[&str], prefix: &str) -> String {
+	match suggest(input, candidates) {
+		Some(candidate) => format!(", did you mean `{}{}`?", prefix, candidate),
+		None => String::new(),
+	}
+}
+
+fn log_sources(format: &ConfigFormat, sources: &BTreeMap<String, &Source>) {
+	let source = |path: &str| {
+		sources
+			.get(path)
+			.copied()
+			.unwrap_or(&Source::File)
+			.to_string()
+	};
+
+	if let Some(build) = &format.build {
+		log::debug!(
+			"build.output = {:?} ({})",
+			build.output,
+			source("build.output")
+		);
+		log::debug!("build.src = {:?} ({})", build.src, source("build.src"));
+		log::debug!(
+			"build.clean = {:?} ({})",
+			build.clean,
+			source("build.clean")
+		);
+		log::debug!(
+			"build.preserve = {:?} ({})",
+			build.preserve,
+			source("build.preserve")
+		);
+	}
+
+	for (key, value) in &format.keys.0 {
+		let path = format!("keys.{}", key);
+		log::debug!("{} = {:?} ({})", path, value, source(&path));
+	}
+
+	for (key, value) in &format.template.0 {
+		let path = format!("template.{}", key);
+		log::debug!("{} = {:?} ({})", path, value, source(&path));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::{Path, PathBuf};
+
+	fn format() -> ConfigFormat {
+		let content =
+			"[build]\noutput = \"build\"\n[keys]\nfoo = \"bar\"\n[template]\ncolor = [\"red\"]";
+		ConfigFormat::parse(content, Path::new("megu.toml")).unwrap()
+	}
+
+	#[test]
+	fn set_build_output() {
+		let mut format = format();
+		let value = Override::from_set("build.output=/tmp/out").unwrap();
+		Overrides::new()
+			.with(vec![value])
+			.apply(&mut format)
+			.unwrap();
+		assert_eq!(format.build.unwrap().output, PathBuf::from("/tmp/out"));
+	}
+
+	#[test]
+	fn define_key_and_pool() {
+		let mut format = format();
+		let overrides = vec![
+			Override::from_define("foo=baz").unwrap(),
+			Override::from_define("color=green, blue").unwrap(),
+		];
+		Overrides::new().with(overrides).apply(&mut format).unwrap();
+
+		assert_eq!(format.keys.0["foo"], "baz");
+		assert_eq!(format.template.0["color"], vec!["green", "blue"]);
+	}
+
+	#[test]
+	fn later_override_wins() {
+		let mut format = format();
+		let env = Override::from_env("MEGU_BUILD_OUTPUT", "env").unwrap();
+		let cli = Override::from_set("build.output=cli").unwrap();
+		Overrides::new()
+			.with(vec![env, cli])
+			.apply(&mut format)
+			.unwrap();
+		assert_eq!(format.build.unwrap().output, PathBuf::from("cli"));
+	}
+
+	#[test]
+	fn env_names() {
+		let value = Override::from_env("MEGU_KEYS_FOO_BAR", "42").unwrap();
+		assert_eq!(value.target, Target::Path("keys.FOO_BAR".into()));
+		let value = Override::from_env("MEGU_BUILD_OUTPUT", "out").unwrap();
+		assert_eq!(value.target, Target::Path("build.output".into()));
+		assert!(Override::from_env("MEGU_LOG", "debug").is_none());
+		assert!(Override::from_env("MEGU_VERSION_X", "2").is_none());
+		assert!(Override::from_env("MEGU_WORKSPACE_MEMBERS", "a").is_none());
+		assert!(Override::from_env("HOME", "/root").is_none());
+	}
+
+	#[test]
+	fn env_matches_existing_case() {
+		let content = "[build]\n[keys]\nfoo = \"a\"\nFooBar = \"b\"\n[template]\ncolor = [\"red\"]";
+		let mut format = ConfigFormat::parse(content, Path::new("megu.toml")).unwrap();
+		let overrides = vec![
+			Override::from_env("MEGU_KEYS_FOO", "x").unwrap(),
+			Override::from_env("MEGU_KEYS_FooBar", "y").unwrap(),
+			Override::from_env("MEGU_TEMPLATE_COLOR", "green,blue").unwrap(),
+			Override::from_env("MEGU_KEYS_NEW", "z").unwrap(),
+		];
+		Overrides::new().with(overrides).apply(&mut format).unwrap();
+
+		assert_eq!(format.keys.0["foo"], "x");
+		assert_eq!(format.keys.0["FooBar"], "y");
+		assert_eq!(format.keys.0["NEW"], "z");
+		assert_eq!(format.keys.0.len(), 3);
+		assert_eq!(format.template.0["color"], vec!["green", "blue"]);
+	}
+
+	#[test]
+	fn unknown_key() {
+		let mut format = format();
+		let value = Override::from_set("build.outptu=/tmp").unwrap();
+		let err = Overrides::new()
+			.with(vec![value])
+			.apply(&mut format)
+			.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Unknown config key `build.outptu` from command line `--set`, did you mean `build.output`?"
+		);
+	}
+
+	#[test]
+	fn not_overridable() {
+		let mut format = format();
+		let value = Override::from_set("build.merge=*.json").unwrap();
+		let err = Overrides::new()
+			.with(vec![value])
+			.apply(&mut format)
+			.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Config key `build.merge` from command line `--set` cannot be overridden, edit it in the config file instead"
+		);
+
+		let value = Override::from_set("build.merg=*.json").unwrap();
+		let err = Overrides::new()
+			.with(vec![value])
+			.apply(&mut format)
+			.unwrap_err();
+		assert!(!err.to_string().contains("did you mean"), "{}", err);
+
+		let value = Override::from_set("workspace.members=a").unwrap();
+		assert!(Overrides::new()
+			.with(vec![value])
+			.apply(&mut format)
+			.is_err());
+	}
+
+	#[test]
+	fn invalid_assignment() {
+		assert!(Override::from_set("build.output").is_err());
+		assert!(Override::from_define("=value").is_err());
+	}
+}
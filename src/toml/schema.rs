@@ -0,0 +1,271 @@
+use super::diagnostic::{locate, suggest, Diagnostic};
+use super::migrate::CURRENT_VERSION;
+use super::{BuildFormat, ConfigFormat, MergeFormat, WorkspaceFormat};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::path::Path;
+use toml::Value;
+
+/// Check the raw config for mistakes that `serde` would either reject without context or silently accept.
+pub fn validate(value: &Value, path: &Path, content: &str) -> Vec<Diagnostic> {
+	let mut result = Vec::new();
+
+	let root = match value.as_table() {
+		Some(root) => root,
+		None => return result,
+	};
+
+	let root_fields = fields::<ConfigFormat>();
+	let mut misspelled_build = false;
+
+	for key in root.keys() {
+		if !root_fields.contains(&key.as_str()) {
+			let span = locate(content, None, key);
+			let suggestion = suggest(key, root_fields);
+			misspelled_build |= matches!(suggestion, Some("build") | Some("workspace"));
+			result.push(unknown_field(key, None, root_fields, path).with_span(span, content));
+		}
+	}
+
+	if let Some(version) = root.get("version").and_then(Value::as_integer) {
+		if version > i64::from(CURRENT_VERSION) {
+			let span = locate(content, None, "version");
+			result.push(newer_version(version, path).with_span(span, content));
+		}
+	}
+
+	// A misspelled `[build]` is already reported above.
+	if !root.contains_key("build") && !root.contains_key("workspace") && !misspelled_build {
+		let help = "add a `[build]` table, or a `[workspace]` table listing member projects";
+		result.push(Diagnostic::new("Missing `[build]` table", path).with_help(help));
+	}
+
+	let build = root.get("build").and_then(Value::as_table);
+	let merge = build
+		.and_then(|build| build.get("merge"))
+		.and_then(Value::as_array)
+		.map(|list| list.iter().filter_map(Value::as_table).collect())
+		.unwrap_or_else(Vec::new);
+
+	let tables = build
+		.into_iter()
+		.map(|table| ("build", "[build]", table, fields::<BuildFormat>()))
+		.chain(
+			root.get("workspace")
+				.and_then(Value::as_table)
+				.map(|table| {
+					(
+						"workspace",
+						"[workspace]",
+						table,
+						fields::<WorkspaceFormat>(),
+					)
+				}),
+		)
+		.chain(merge.into_iter().map(|table| {
+			(
+				"build.merge",
+				"[[build.merge]]",
+				table,
+				fields::<MergeFormat>(),
+			)
+		}));
+
+	for (name, header, table, expected) in tables {
+		for key in table.keys() {
+			if !expected.contains(&key.as_str()) {
+				let span = locate(content, Some(name), key);
+				let diagnostic = unknown_field(key, Some(header), expected, path);
+				result.push(diagnostic.with_span(span, content));
+			}
+		}
+	}
+
+	if let Some(template) = root.get("template").and_then(Value::as_table) {
+		for (key, value) in template {
+			if value.as_array().map(Vec::is_empty).unwrap_or(false) {
+				let span = locate(content, Some("template"), key);
+				result.push(empty_pool(key, path).with_span(span, content));
+			}
+		}
+	}
+
+	result
+}
+
+/// Names of the fields `T` accepts, read from its `Deserialize` implementation so they can't drift from the struct.
+pub fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+	let mut fields: &'static [&'static str] = &[];
+	let _ = T::deserialize(FieldNames(&mut fields));
+	fields
+}
+
+/// A deserializer that only records the field names it's asked for, then gives up.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+	type Error = de::value::Error;
+
+	fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+		Err(de::Error::custom("not a struct"))
+	}
+
+	fn deserialize_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		fields: &'static [&'static str],
+		_visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		*self.0 = fields;
+		Err(de::Error::custom("only the field names are needed"))
+	}
+
+	serde::forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+		unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+	}
+}
+
+/// `header` is the table as written in the config, like `[build]`.
+fn unknown_field(key: &str, header: Option<&str>, expected: &[&str], path: &Path) -> Diagnostic {
+	let message = match header {
+		Some(header) => format!("Unknown field `{}` in `{}`", key, header),
+		None => format!("Unknown table `[{}]`", key),
+	};
+
+	let help = match suggest(key, expected) {
+		Some(candidate) => format!("did you mean `{}`?", candidate),
+		None => format!("expected one of {}", quote_all(expected)),
+	};
+
+	Diagnostic::new(message, path).with_help(help)
+}
+
+fn empty_pool(key: &str, path: &Path) -> Diagnostic {
+	let message = format!("Template pool `{}` is empty", key);
+	let help = format!(
+		"any path containing `[{}]` would produce no output, add at least one value",
+		key
+	);
+	Diagnostic::new(message, path).with_help(help)
+}
+
+fn newer_version(version: i64, path: &Path) -> Diagnostic {
+	let message = format!(
+		"Config version {} is newer than the latest supported version {}",
+		version, CURRENT_VERSION
+	);
+	Diagnostic::new(message, path).with_help("upgrade megumax to build this project")
+}
+
+fn quote_all(list: &[&str]) -> String {
+	list.iter()
+		.map(|s| format!("`{}`", s))
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::toml::diagnostic::{Diagnostics, Span};
+
+	fn check(content: &str) -> Vec<Diagnostic> {
+		match ConfigFormat::parse(content, Path::new("megu.toml")) {
+			Ok(_) => vec![],
+			Err(Diagnostics(list)) => list,
+		}
+	}
+
+	#[test]
+	fn misspelled_table() {
+		let result = check("[biuld]\noutput = \"build\"\n");
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].message, "Unknown table `[biuld]`");
+		assert_eq!(result[0].help.as_deref(), Some("did you mean `build`?"));
+		assert_eq!(result[0].span, Some(Span::new(0, 1, 5)));
+	}
+
+	#[test]
+	fn misspelled_field() {
+		let result = check("[build]\nsrc = \"src\"\noutptu = \"build\"\n");
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].message, "Unknown field `outptu` in `[build]`");
+		assert_eq!(result[0].help.as_deref(), Some("did you mean `output`?"));
+		assert_eq!(result[0].span, Some(Span::new(2, 0, 6)));
+	}
+
+	#[test]
+	fn code_frame() {
+		let result = check("[build]\n  outptu = \"build\"\n");
+		let expect = "Unknown field `outptu` in `[build]`
+ --> megu.toml:2:3
+  |
+2 |   outptu = \"build\"
+  |   ^^^^^^
+  = help: did you mean `output`?";
+		assert_eq!(result[0].to_string(), expect);
+	}
+
+	#[test]
+	fn misspelled_merge_field() {
+		let content =
+			"[build]\n\n[[build.merge]]\nfiles = \"*.json\"\nstrategi = \"append-lines\"\n";
+		let result = check(content);
+		assert_eq!(result.len(), 1);
+		assert_eq!(
+			result[0].message,
+			"Unknown field `strategi` in `[[build.merge]]`"
+		);
+		assert_eq!(result[0].help.as_deref(), Some("did you mean `strategy`?"));
+		assert_eq!(result[0].span, Some(Span::new(4, 0, 8)));
+	}
+
+	#[test]
+	fn derived_fields() {
+		assert_eq!(
+			fields::<ConfigFormat>(),
+			["version", "template", "keys", "build", "workspace"]
+		);
+		assert_eq!(fields::<WorkspaceFormat>(), ["members"]);
+	}
+
+	#[test]
+	fn empty_pool() {
+		let result = check("[build]\n[template]\ncolor = []\nshape = [\"circle\"]\n");
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].message, "Template pool `color` is empty");
+		assert_eq!(result[0].span, Some(Span::new(2, 0, 5)));
+	}
+
+	#[test]
+	fn syntax_error() {
+		let result = check("[build]\noutput = \n");
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].span.map(|s| s.line), Some(1));
+	}
+
+	#[test]
+	fn missing_build() {
+		let result = check("[keys]\nfoo = \"bar\"\n");
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].message, "Missing `[build]` table");
+
+		let result = check("[workspace]\nmembers = [\"packs/*\"]\n");
+		assert!(result.is_empty());
+	}
+
+	#[test]
+	fn newer_config_version() {
+		let result = check("version = 999\n[build]\n");
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].span, Some(Span::new(0, 0, 7)));
+	}
+
+	#[test]
+	fn valid_config() {
+		let result = check(
+			"[build]\noutput = \"out\"\n[keys]\nfoo = \"bar\"\n[template]\ncolor = [\"red\"]\n",
+		);
+		assert!(result.is_empty());
+	}
+}
@@ -73,7 +73,7 @@ where
 	fn parse(&mut self) -> Result<String> {
 		let range = self.possible_range();
 		let buffer = &mut self.buffer;
-		let result = from_utf8(&buffer).map(|s| s.to_owned());
+		let result = from_utf8(buffer).map(|s| s.to_owned());
 
 		let result = match result {
 			Ok(v) => Ok(v),
@@ -114,7 +114,7 @@ where
 	}
 
 	fn should_stop(&self, input: &Result<String>) -> bool {
-		self.ended || input.as_ref().map(|s| (self.f)(&s)).unwrap_or(true)
+		self.ended || input.as_ref().map(|s| (self.f)(s)).unwrap_or(true)
 	}
 
 	fn reinsert_content(&mut self, content: &str) {
@@ -130,6 +130,8 @@ mod imports {
 	pub use std::io::{self, Bytes, Read};
 	use std::iter::FusedIterator;
 
+	// Callers are expected to hand over a buffered reader.
+	#[allow(clippy::unbuffered_bytes)]
 	pub fn create_reader<R: Read>(rdr: R) -> Bytes<R> {
 		rdr.bytes()
 	}
@@ -0,0 +1,354 @@
+use crate::config::Config;
+use crate::config::{read_format, CONFIG_FILE};
+use crate::error::ConfigError;
+use crate::share::stringify_path;
+use crate::toml::diagnostic::suggest;
+use crate::toml::overrides::Overrides;
+use crate::toml::ConfigFormat;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// A project that is built as part of a workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+	/// Directory of the member relative to the workspace root, `.` for the root itself.
+	pub name: String,
+	pub config_path: PathBuf,
+}
+
+/// A config file with a `[workspace]` table, building every member project at once.
+///
+/// `[keys]` and `[template]` entries of the workspace root are inherited by every member.
+#[derive(Debug)]
+pub struct Workspace {
+	pub config_path: PathBuf,
+	pub format: ConfigFormat,
+	pub members: Vec<Member>,
+}
+
+impl Workspace {
+	/// Load the workspace defined at `path`, or `None` if that config isn't a workspace root.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+		let config_path = path.as_ref().to_path_buf();
+		let format = read_format(&config_path)?;
+
+		let patterns = match &format.workspace {
+			Some(workspace) => workspace.members.clone(),
+			None => return Ok(None),
+		};
+
+		let root = config_path.parent().unwrap_or_else(|| Path::new(""));
+		let mut members = Vec::new();
+
+		if format.build.is_some() {
+			members.push(Member {
+				name: ".".into(),
+				config_path: config_path.clone(),
+			});
+		}
+
+		for pattern in &patterns {
+			expand_member(root, pattern, &mut members).with_context(|| {
+				format!("Resolving workspace members of `{}`", config_path.display())
+			})?;
+		}
+
+		log::debug!("Found {} workspace members", members.len());
+
+		let workspace = Self {
+			config_path,
+			format,
+			members,
+		};
+		Ok(Some(workspace))
+	}
+
+	/// Find a workspace in the parent directories that lists the project at `path` as a member.
+	///
+	/// Parent configs that fail to load are skipped.
+	pub fn find_parent<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+		let path = path.as_ref();
+		let canonical = match path.canonicalize() {
+			Ok(canonical) => canonical,
+			Err(_) => return Ok(None),
+		};
+
+		let parents = canonical
+			.parent()
+			.into_iter()
+			.flat_map(Path::ancestors)
+			.skip(1);
+
+		for dir in parents {
+			let candidate = dir.join(CONFIG_FILE);
+			if !candidate.is_file() {
+				continue;
+			}
+
+			// A parent that can't be loaded is most likely unrelated, it only matters once it lists this project.
+			let workspace = match Self::load(&candidate) {
+				Ok(Some(workspace)) => workspace,
+				Ok(None) => continue,
+				Err(err) => {
+					log::debug!("Skipped {}: {:#}", candidate.display(), err);
+					continue;
+				}
+			};
+
+			if workspace.member_of(&canonical).is_some() {
+				log::debug!("{} is a member of {}", path.display(), candidate.display());
+				return Ok(Some(workspace));
+			}
+		}
+
+		Ok(None)
+	}
+
+	fn member_of(&self, canonical: &Path) -> Option<&Member> {
+		self.members
+			.iter()
+			.find(|member| member.config_path.canonicalize().ok().as_deref() == Some(canonical))
+	}
+
+	/// Pick members by name, or every member if `names` is empty.
+	pub fn select(&self, names: &[String]) -> Result<Vec<&Member>> {
+		if names.is_empty() {
+			return Ok(self.members.iter().collect());
+		}
+
+		names.iter().map(|name| self.find_member(name)).collect()
+	}
+
+	fn find_member(&self, name: &str) -> Result<&Member> {
+		let found = self
+			.members
+			.iter()
+			.find(|member| member.name == name || short_name(&member.name) == name);
+
+		if let Some(member) = found {
+			return Ok(member);
+		}
+
+		let names: Vec<&str> = self.members.iter().map(|m| short_name(&m.name)).collect();
+		let candidate = suggest(name, &names).and_then(|candidate| {
+			self.members
+				.iter()
+				.find(|m| short_name(&m.name) == candidate)
+		});
+
+		match candidate {
+			Some(member) => bail!(ConfigError::new(format!(
+				"Cannot find workspace member `{}`, did you mean `{}`?",
+				name, member.name
+			))),
+			None => bail!(ConfigError::new(format!(
+				"Cannot find workspace member `{}`",
+				name
+			))),
+		}
+	}
+
+	/// Load the config of a member project, inheriting shared values from the workspace root.
+	pub fn load_member<P: AsRef<Path>>(&self, path: P, overrides: &Overrides) -> Result<Config> {
+		let path = path.as_ref();
+		let mut format = read_format(path)?;
+
+		if format.workspace.is_some() && path != self.config_path {
+			bail!(ConfigError::new(format!(
+				"`{}` is a member of `{}` and cannot define a workspace of its own",
+				path.display(),
+				self.config_path.display()
+			)));
+		}
+
+		format.inherit(&self.format);
+		overrides.apply(&mut format)?;
+		format.compile(path.to_path_buf())
+	}
+}
+
+/// Config path of the single project a command works on, either the one at `path` or a member of the workspace there.
+///
+/// At a workspace root the member has to be picked with `--package`, unless it only has one.
+pub fn single_project<P: AsRef<Path>>(path: P, packages: &[String]) -> Result<PathBuf> {
+	let path = path.as_ref();
+
+	let workspace = match Workspace::load(path)? {
+		Some(workspace) => workspace,
+		None if packages.is_empty() => return Ok(path.to_path_buf()),
+		None => bail!(ConfigError::new(
+			"`--package` can only be used inside a workspace"
+		)),
+	};
+
+	match workspace.select(packages)?.as_slice() {
+		[member] => Ok(member.config_path.clone()),
+		members => {
+			let names: Vec<_> = members
+				.iter()
+				.map(|member| format!("`{}`", member.name))
+				.collect();
+			bail!(ConfigError::new(format!(
+				"`{}` is a workspace, this command needs a single member picked with `--package`: {}",
+				path.display(),
+				names.join(", ")
+			)))
+		}
+	}
+}
+
+/// The last component of a member name, `packs/alpha` can be selected as just `alpha`.
+fn short_name(name: &str) -> &str {
+	name.rsplit('/').next().unwrap_or(name)
+}
+
+fn expand_member(root: &Path, pattern: &str, members: &mut Vec<Member>) -> Result<()> {
+	let full = root.join(pattern);
+	let full = stringify_path(&full)?;
+	let mut matched = false;
+
+	for dir in glob::glob(full).with_context(|| format!("Invalid member pattern `{}`", pattern))? {
+		let dir = dir?;
+		let config_path = dir.join(CONFIG_FILE);
+
+		if !config_path.is_file() {
+			log::debug!(
+				"Skipped {}, it doesn't contain `{}`",
+				dir.display(),
+				CONFIG_FILE
+			);
+			continue;
+		}
+
+		matched = true;
+
+		if members
+			.iter()
+			.any(|member| member.config_path == config_path)
+		{
+			continue;
+		}
+
+		let name = dir.strip_prefix(root).unwrap_or(&dir);
+		let name = name.to_string_lossy().replace('\\', "/");
+		members.push(Member { name, config_path });
+	}
+
+	if !matched {
+		bail!(ConfigError::new(format!(
+			"Member `{}` doesn't match any directory containing `{}`",
+			pattern, CONFIG_FILE
+		)));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+	use tempfile::TempDir;
+
+	fn create_workspace() -> TempDir {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+
+		for pack in &["alpha", "beta"] {
+			let dir = root.join("packs").join(pack);
+			fs::create_dir_all(&dir).unwrap();
+			fs::write(dir.join(CONFIG_FILE), "[build]\n[keys]\nname = \"member\"").unwrap();
+		}
+
+		fs::create_dir_all(root.join("packs").join("not_a_member")).unwrap();
+
+		let content =
+			"[workspace]\nmembers = [\"packs/*\"]\n[keys]\nname = \"root\"\nshared = \"yes\"";
+		fs::write(root.join(CONFIG_FILE), content).unwrap();
+		dir
+	}
+
+	#[test]
+	fn expand_members() {
+		let dir = create_workspace();
+		let root = dir.path();
+		let workspace = Workspace::load(root.join(CONFIG_FILE)).unwrap().unwrap();
+		let names: Vec<_> = workspace.members.iter().map(|m| m.name.as_str()).collect();
+
+		assert_eq!(names, vec!["packs/alpha", "packs/beta"]);
+	}
+
+	#[test]
+	fn select_members() {
+		let dir = create_workspace();
+		let root = dir.path();
+		let workspace = Workspace::load(root.join(CONFIG_FILE)).unwrap().unwrap();
+
+		let selected = workspace.select(&["beta".into()]).unwrap();
+		assert_eq!(selected[0].name, "packs/beta");
+
+		let err = workspace.select(&["alpah".into()]).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Cannot find workspace member `alpah`, did you mean `packs/alpha`?"
+		);
+	}
+
+	#[test]
+	fn pick_single_project() {
+		let dir = create_workspace();
+		let root = dir.path().join(CONFIG_FILE);
+
+		let err = single_project(&root, &[]).unwrap_err();
+		assert!(
+			err.to_string().contains("`packs/alpha`, `packs/beta`"),
+			"{}",
+			err
+		);
+		assert!(single_project(&root, &["alpha".into(), "beta".into()]).is_err());
+
+		let member = single_project(&root, &["beta".into()]).unwrap();
+		assert_eq!(
+			member,
+			dir.path().join("packs").join("beta").join(CONFIG_FILE)
+		);
+		assert_eq!(single_project(&member, &[]).unwrap(), member);
+		assert!(single_project(&member, &["beta".into()]).is_err());
+	}
+
+	#[test]
+	fn skip_broken_parent() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		let child = root.join("child");
+		fs::create_dir_all(&child).unwrap();
+		fs::write(child.join(CONFIG_FILE), "[build]").unwrap();
+
+		fs::write(root.join(CONFIG_FILE), "[biuld]").unwrap();
+		assert!(Workspace::find_parent(child.join(CONFIG_FILE))
+			.unwrap()
+			.is_none());
+
+		fs::write(
+			root.join(CONFIG_FILE),
+			"[workspace]\nmembers = [\"packs/*\"]",
+		)
+		.unwrap();
+		assert!(Workspace::find_parent(child.join(CONFIG_FILE))
+			.unwrap()
+			.is_none());
+	}
+
+	#[test]
+	fn inherit_shared_keys() {
+		let dir = create_workspace();
+		let root = dir.path();
+		let member = root.join("packs").join("alpha").join(CONFIG_FILE);
+
+		let workspace = Workspace::find_parent(&member).unwrap().unwrap();
+		let config = workspace.load_member(&member, &Overrides::new()).unwrap();
+
+		assert_eq!(config.keys.replace("[name] [shared]"), "member yes");
+		assert_eq!(config.source, root.join("packs").join("alpha").join("src"));
+	}
+}
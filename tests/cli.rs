@@ -0,0 +1,188 @@
+//! Exit codes of the `megu` binary, which scripts and CI rely on.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Run `megu` quietly in `dir` and return its exit code.
+fn megu(dir: &Path, args: &[&str]) -> i32 {
+	let output = Command::new(env!("CARGO_BIN_EXE_megu"))
+		.current_dir(dir)
+		.arg("--quiet")
+		.args(args)
+		.output()
+		.unwrap();
+	output.status.code().unwrap()
+}
+
+/// Run `megu` in `dir` with a stdout nobody reads, like `megu ... | head` once `head` has enough lines.
+fn megu_closed_stdout(dir: &Path, args: &[&str]) -> i32 {
+	let (reader, writer) = std::io::pipe().unwrap();
+	drop(reader);
+
+	let output = Command::new(env!("CARGO_BIN_EXE_megu"))
+		.current_dir(dir)
+		.args(args)
+		.stdout(writer)
+		.output()
+		.unwrap();
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(!stderr.contains("panicked"), "{}", stderr);
+	output.status.code().unwrap()
+}
+
+fn create_project(root: &Path) {
+	fs::create_dir_all(root.join("src")).unwrap();
+	fs::write(root.join("megu.toml"), "[build]\n[keys]\nname = \"megu\"\n").unwrap();
+	fs::write(root.join("src").join("a.txt"), "[name]").unwrap();
+}
+
+#[test]
+fn verify_exit_code() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	create_project(root);
+
+	assert_eq!(megu(root, &["verify"]), 10);
+	assert_eq!(megu(root, &["build"]), 0);
+	assert_eq!(megu(root, &["verify"]), 0);
+
+	fs::write(root.join("build").join("a.txt"), "edited").unwrap();
+	assert_eq!(megu(root, &["verify"]), 10);
+}
+
+#[test]
+fn diff_exit_code() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	create_project(root);
+
+	assert_eq!(megu(root, &["diff"]), 1);
+	assert_eq!(megu(root, &["build"]), 0);
+	assert_eq!(megu(root, &["diff"]), 0);
+}
+
+#[test]
+fn workspace_exit_code() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	fs::write(
+		root.join("megu.toml"),
+		"[workspace]\nmembers = [\"packs/*\"]\n",
+	)
+	.unwrap();
+	create_project(&root.join("packs").join("a"));
+	create_project(&root.join("packs").join("b"));
+	fs::write(root.join("packs/a/src/[missing].txt"), "").unwrap();
+
+	// The build stops at the first failing member.
+	assert_eq!(megu(root, &["build"]), 4);
+	assert!(!root.join("packs/b/build").exists());
+
+	assert_eq!(megu(root, &["build", "--keep-going"]), 4);
+	assert!(root.join("packs/b/build/a.txt").is_file());
+
+	assert_eq!(megu(root, &["build", "-p", "b"]), 0);
+}
+
+#[test]
+fn workspace_json_events() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	fs::write(
+		root.join("megu.toml"),
+		"[workspace]\nmembers = [\"packs/*\"]\n",
+	)
+	.unwrap();
+	create_project(&root.join("packs").join("a"));
+	fs::write(root.join("packs/a/src/[missing].txt"), "").unwrap();
+
+	for keep_going in &[false, true] {
+		let mut command = Command::new(env!("CARGO_BIN_EXE_megu"));
+		command
+			.current_dir(root)
+			.args(["build", "--message-format", "json"]);
+		if *keep_going {
+			command.arg("--keep-going");
+		}
+
+		let output = command.output().unwrap();
+		let stdout = String::from_utf8(output.stdout).unwrap();
+		let errors = stdout
+			.lines()
+			.filter(|line| line.contains(r#""event":"error""#))
+			.count();
+		assert_eq!(errors, 1, "{}", stdout);
+		assert!(
+			stdout.lines().all(|line| line.starts_with('{')),
+			"{}",
+			stdout
+		);
+		assert!(stdout.contains(r#"{"event":"member","name":"packs/a"}"#));
+	}
+}
+
+#[test]
+fn unmarked_output_exit_code() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	create_project(root);
+	fs::create_dir(root.join("build")).unwrap();
+	fs::write(root.join("build").join("notes.txt"), "mine").unwrap();
+
+	assert_eq!(megu(root, &["build"]), 3);
+	assert_eq!(megu(root, &["build", "--force"]), 0);
+}
+
+#[test]
+fn lint_exit_code() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	create_project(root);
+	fs::write(root.join("src").join("list.json"), "[0]").unwrap();
+
+	assert_eq!(megu(root, &["lint"]), 0);
+
+	fs::write(root.join("src").join("[missing].txt"), "").unwrap();
+	assert_eq!(megu(root, &["lint"]), 1);
+}
+
+#[test]
+fn closed_stdout() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	create_project(root);
+
+	assert_eq!(
+		megu_closed_stdout(root, &["plan", "--message-format", "json"]),
+		0
+	);
+	assert_eq!(
+		megu_closed_stdout(root, &["lint", "--message-format", "json"]),
+		0
+	);
+	assert_eq!(
+		megu_closed_stdout(root, &["explain", "src/a.txt", "--message-format", "json"]),
+		0
+	);
+	assert_eq!(megu_closed_stdout(root, &["diff"]), 1);
+	assert_eq!(
+		megu_closed_stdout(root, &["build", "--message-format", "json"]),
+		0
+	);
+}
+
+#[test]
+fn quiet_migrate_needs_confirmation() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	let config = root.join("megu.toml");
+	fs::write(&config, "[build]\n").unwrap();
+
+	assert_eq!(megu(root, &["migrate"]), 2);
+	assert_eq!(fs::read_to_string(&config).unwrap(), "[build]\n");
+
+	assert_eq!(megu(root, &["migrate", "--yes"]), 0);
+	assert_ne!(fs::read_to_string(&config).unwrap(), "[build]\n");
+	assert_eq!(megu(root, &["migrate"]), 0);
+}